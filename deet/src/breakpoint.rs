@@ -0,0 +1,318 @@
+use crate::dwarf_data::{DwarfData, Type};
+use crate::expr::{self, Expr};
+use crate::inferior::Inferior;
+use crate::location::Frame;
+use crate::watchpoint::{Scope, WatchKind, Watchpoint};
+use nix::sys::ptrace;
+use std::fmt;
+
+/// A user breakpoint created with the `break` or `tbreak` command.
+pub struct Breakpoint {
+    pub num: usize,
+    pub addr: u64,
+    /// The location exactly as the user specified it (e.g. `func2`, `12` or `*0x401136`)
+    pub location: String,
+    pub enabled: bool,
+    /// Deleted the first time it is hit (`tbreak`)
+    pub temporary: bool,
+    /// The number of hits that count, but do not stop the inferior, before it stops again
+    /// (`ignore`)
+    pub ignore_count: u64,
+    pub hit_count: u64,
+    /// Only stop when this condition holds (`break <loc> if <cond>`)
+    pub condition: Option<Condition>,
+    /// Command lines to run whenever the inferior stops here (`commands`)
+    pub commands: Vec<String>,
+}
+
+/// The numbered list of user breakpoints and watchpoints, which share one numbering. It outlives
+/// any single inferior, so breakpoints are re-inserted every time the program is (re)started.
+#[derive(Default)]
+pub struct Breakpoints {
+    list: Vec<Breakpoint>,
+    watchpoints: Vec<Watchpoint>,
+    next_num: usize,
+    /// The breakpoints that made the inferior stop last, until the stop is reported
+    hits: Vec<usize>,
+    /// Set when watchpoints made the inferior stop, until the stop is reported
+    watch_stop: bool,
+}
+
+impl Breakpoints {
+    pub fn new() -> Breakpoints {
+        Default::default()
+    }
+
+    /// Creates a new enabled breakpoint and returns it.
+    pub fn add(
+        &mut self,
+        addr: u64,
+        location: &str,
+        condition: Option<Condition>,
+        temporary: bool,
+    ) -> &Breakpoint {
+        self.list.push(Breakpoint {
+            num: self.next_num,
+            addr,
+            location: location.to_string(),
+            enabled: true,
+            temporary,
+            ignore_count: 0,
+            hit_count: 0,
+            condition,
+            commands: Vec::new(),
+        });
+        self.next_num += 1;
+        self.list.last().unwrap()
+    }
+
+    /// Creates a new enabled watchpoint on the value of `entity_type` stored at addr. It is not
+    /// inserted into the inferior yet. Values that no debug register can cover are watched in
+    /// software from the start.
+    pub fn add_watchpoint(
+        &mut self,
+        kind: WatchKind,
+        expression: &str,
+        addr: u64,
+        entity_type: Type,
+        scope: Option<Scope>,
+    ) -> &mut Watchpoint {
+        let software = !matches!(entity_type.size, 1 | 2 | 4 | 8);
+        self.watchpoints.push(Watchpoint {
+            num: self.next_num,
+            kind,
+            expression: expression.to_string(),
+            addr,
+            entity_type,
+            enabled: true,
+            hit_count: 0,
+            slot: None,
+            software,
+            scope,
+            old_value: Vec::new(),
+        });
+        self.next_num += 1;
+        self.watchpoints.last_mut().unwrap()
+    }
+
+    pub fn remove(&mut self, num: usize) -> Option<Breakpoint> {
+        let index = self.list.iter().position(|bp| bp.num == num)?;
+        Some(self.list.remove(index))
+    }
+
+    pub fn remove_watchpoint(&mut self, num: usize) -> Option<Watchpoint> {
+        let index = self.watchpoints.iter().position(|wp| wp.num == num)?;
+        Some(self.watchpoints.remove(index))
+    }
+
+    pub fn get_mut(&mut self, num: usize) -> Option<&mut Breakpoint> {
+        self.list.iter_mut().find(|bp| bp.num == num)
+    }
+
+    pub fn get_watchpoint_mut(&mut self, num: usize) -> Option<&mut Watchpoint> {
+        self.watchpoints.iter_mut().find(|wp| wp.num == num)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Breakpoint> {
+        self.list.iter()
+    }
+
+    pub fn watchpoints(&self) -> impl Iterator<Item = &Watchpoint> {
+        self.watchpoints.iter()
+    }
+
+    pub fn watchpoints_mut(&mut self) -> impl Iterator<Item = &mut Watchpoint> {
+        self.watchpoints.iter_mut()
+    }
+
+    /// Returns the number of the breakpoint or watchpoint created last, even if it has been
+    /// deleted since.
+    pub fn last_num(&self) -> Option<usize> {
+        self.next_num.checked_sub(1)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.list.is_empty() && self.watchpoints.is_empty()
+    }
+
+    /// Returns the numbers of all breakpoints and watchpoints, in the order they were created.
+    pub fn nums(&self) -> Vec<usize> {
+        let mut nums: Vec<usize> = self.list.iter().map(|bp| bp.num).collect();
+        nums.extend(self.watchpoints.iter().map(|wp| wp.num));
+        nums.sort_unstable();
+        nums
+    }
+
+    /// Returns true if some enabled breakpoint, or the scope of an enabled watchpoint, still
+    /// needs a trap at addr.
+    pub fn is_enabled_at(&self, addr: u64) -> bool {
+        self.list.iter().any(|bp| bp.enabled && bp.addr == addr) || self.is_scope_at(addr)
+    }
+
+    fn is_scope_at(&self, addr: u64) -> bool {
+        self.watchpoints
+            .iter()
+            .any(|wp| wp.enabled && wp.scope.is_some_and(|scope| scope.return_addr == addr))
+    }
+
+    /// Returns true if the inferior has to be single-stepped to check software watchpoints.
+    pub fn has_software_watchpoints(&self) -> bool {
+        self.watchpoints.iter().any(|wp| wp.software && wp.enabled)
+    }
+
+    /// Returns the addresses that need a trap inserted into a freshly started inferior.
+    pub fn enabled_addrs(&self) -> Vec<u64> {
+        self.list
+            .iter()
+            .filter(|bp| bp.enabled)
+            .map(|bp| bp.addr)
+            .collect()
+    }
+
+    /// Decides whether the inferior, which just trapped at addr, should stop there. Breakpoints
+    /// whose condition is false are skipped; the others count a hit, and stop unless they are
+    /// still ignoring hits. The debugger's internal traps are handled by the caller.
+    pub fn should_stop(&mut self, addr: u64, inferior: &Inferior, debug_data: &DwarfData) -> bool {
+        let mut stop = false;
+        self.hits.clear();
+        for bp in self
+            .list
+            .iter_mut()
+            .filter(|bp| bp.enabled && bp.addr == addr)
+        {
+            let triggered = match &bp.condition {
+                None => true,
+                Some(condition) => match condition.evaluate(inferior, debug_data) {
+                    Ok(value) => value,
+                    Err(err) => {
+                        println!(
+                            "Error in testing condition for breakpoint {}: {}",
+                            bp.num, err
+                        );
+                        true
+                    }
+                },
+            };
+            if !triggered {
+                continue;
+            }
+            bp.hit_count += 1;
+            if bp.ignore_count > 0 {
+                bp.ignore_count -= 1;
+            } else {
+                self.hits.push(bp.num);
+                stop = true;
+            }
+        }
+        stop
+    }
+
+    /// Returns true if user breakpoints or watchpoints made the inferior stop and the stop has
+    /// not been reported yet.
+    pub fn has_hits(&self) -> bool {
+        !self.hits.is_empty() || self.watch_stop
+    }
+
+    /// Returns the breakpoints that made the inferior stop, if they have not been reported yet.
+    pub fn take_hits(&mut self) -> Vec<usize> {
+        self.watch_stop = false;
+        std::mem::take(&mut self.hits)
+    }
+
+    /// Called when the inferior stopped after hardware watchpoints fired or after a single step
+    /// made for software watchpoints; `triggers` holds the DR6 status bits of the debug
+    /// registers involved. Reports each watchpoint that triggered and returns whether the
+    /// inferior should stop.
+    pub fn check_watchpoints(
+        &mut self,
+        triggers: u8,
+        inferior: &Inferior,
+        debug_data: &DwarfData,
+    ) -> bool {
+        let mut stop = false;
+        for wp in self.watchpoints.iter_mut() {
+            let fired = match wp.slot {
+                Some(slot) => triggers & (1 << slot) != 0,
+                None => wp.software && wp.enabled,
+            };
+            if fired && wp.triggered(inferior, debug_data) {
+                stop = true;
+            }
+        }
+        self.watch_stop |= stop;
+        stop
+    }
+
+    /// Deletes the watchpoints on local variables whose frame has returned, which is the case
+    /// once the stack pointer is back at the frame's CFA. Returns true if any was deleted.
+    pub fn leave_scopes(&mut self, inferior: &mut Inferior) -> Result<bool, nix::Error> {
+        let rsp = ptrace::getregs(inferior.pid())?.rsp;
+        let mut left = Vec::new();
+        let mut index = 0;
+        while index < self.watchpoints.len() {
+            match self.watchpoints[index].scope {
+                Some(scope) if rsp >= scope.cfa => left.push(self.watchpoints.remove(index)),
+                _ => index += 1,
+            }
+        }
+        for wp in left.iter_mut() {
+            wp.remove(inferior)?;
+            let return_addr = wp.scope.unwrap().return_addr;
+            if !self.is_enabled_at(return_addr) {
+                inferior.remove_breakpoint(return_addr)?;
+            }
+            print_scope_left(wp);
+        }
+        self.watch_stop |= !left.is_empty();
+        Ok(!left.is_empty())
+    }
+
+    /// Deletes all watchpoints on local variables, whose frames are gone once the inferior has
+    /// exited.
+    pub fn remove_scoped_watchpoints(&mut self) {
+        self.watchpoints.retain(|wp| {
+            if wp.scope.is_some() {
+                print_scope_left(wp);
+            }
+            wp.scope.is_none()
+        });
+    }
+}
+
+fn print_scope_left(wp: &Watchpoint) {
+    println!();
+    println!(
+        "Watchpoint {} deleted because the program has left the block in",
+        wp.num
+    );
+    println!("which its expression is valid.");
+}
+
+/// A breakpoint condition: a C expression that is true when it evaluates to nonzero.
+pub struct Condition {
+    text: String,
+    expr: Expr,
+}
+
+impl Condition {
+    pub fn parse(text: &str, debug_data: &DwarfData) -> Result<Condition, String> {
+        let text = text.trim();
+        Ok(Condition {
+            text: text.to_string(),
+            expr: expr::parse(text, debug_data)?,
+        })
+    }
+
+    /// Evaluates the condition against the inferior's current frame.
+    pub fn evaluate(&self, inferior: &Inferior, debug_data: &DwarfData) -> Result<bool, String> {
+        let frame = Frame::innermost(inferior, debug_data).map_err(|e| e.to_string())?;
+        let value = expr::evaluate(&self.expr, &frame)?;
+        expr::is_nonzero(&value, inferior, debug_data)
+    }
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.text)
+    }
+}
@@ -1,10 +1,20 @@
+use crate::breakpoint::{Breakpoints, Condition};
 use crate::debugger_command::DebuggerCommand;
-use crate::dwarf_data::{DwarfData, Error as DwarfError, Location}; // Import Location
+use crate::dwarf_data::{DwarfData, Error as DwarfError, Location, Type, TypeKind};
+use crate::examine::{Examiner, Spec};
+use crate::expr::{self, Destination, Expr};
 use crate::inferior::{Inferior, Status};
+use crate::location::Frame;
+use crate::registers;
+use crate::unwind::{self, StackFrame};
+use crate::value;
+use crate::watchpoint::{Scope, WatchKind};
 use nix::sys::ptrace;
+use nix::sys::signal::Signal;
 use rustyline::error::ReadlineError;
 use rustyline::history::FileHistory;
 use rustyline::Editor;
+use std::collections::VecDeque;
 use std::fs::File;
 use std::io::{BufRead, BufReader};
 
@@ -14,13 +24,20 @@ pub struct Debugger {
     readline: Editor<(), FileHistory>,
     inferior: Option<Inferior>,
     debug_data: DwarfData,
-    breakpoints: Vec<u64>,
+    breakpoints: Breakpoints,
+    /// The level of the frame `print` and `watch` look up variables in, counting outwards from
+    /// the innermost frame (0). Reset whenever the inferior stops.
+    selected_frame: usize,
+    examiner: Examiner,
+    /// Command lines of the breakpoints the inferior last stopped at, still to be run
+    pending_commands: VecDeque<String>,
+    /// The temporary breakpoint at main made by the last `start`, until the next `start`
+    start_breakpoint: Option<usize>,
 }
 
 impl Debugger {
     /// Initializes the debugger.
     pub fn new(target: &str) -> Debugger {
-        // TODO (milestone 3): initialize the DwarfData
         let debug_data = match DwarfData::from_file(target) {
             Ok(val) => val,
             Err(DwarfError::ErrorOpeningFile) => {
@@ -53,214 +70,1063 @@ impl Debugger {
             readline,
             inferior: None,
             debug_data,
-            breakpoints: Vec::new(),
+            breakpoints: Breakpoints::new(),
+            selected_frame: 0,
+            examiner: Examiner::new(),
+            pending_commands: VecDeque::new(),
+            start_breakpoint: None,
         }
     }
 
     pub fn run(&mut self) {
         loop {
-            match self.get_next_command() {
-                DebuggerCommand::Run(args) => {
-                    if let Some(inferior) = self.inferior.as_mut() {
-                        inferior.kill();
-                        self.inferior = None;
+            // The commands of a breakpoint that was just hit run before the user is asked
+            let command = match self.pending_commands.pop_front() {
+                Some(line) => {
+                    let tokens: Vec<&str> = line.split_whitespace().collect();
+                    match DebuggerCommand::from_tokens(&tokens) {
+                        Some(command) => command,
+                        None => {
+                            println!("Unrecognized command \"{}\".", line);
+                            continue;
+                        }
+                    }
+                }
+                None => self.get_next_command(),
+            };
+            if !self.execute(command) {
+                return;
+            }
+        }
+    }
+
+    /// Carries out a command, whether the user typed it or it is part of a breakpoint's command
+    /// list. Returns false once the debugger should exit.
+    fn execute(&mut self, command: DebuggerCommand) -> bool {
+        match command {
+            DebuggerCommand::Run(args) => {
+                self.run_inferior(&args);
+            }
+            DebuggerCommand::Start(args) => {
+                // The breakpoint of an earlier `start` is left over if main was never reached
+                self.remove_start_breakpoint();
+                // Stop at main's first line, after its prologue, like `tbreak main`
+                if self.add_breakpoint("main", None, true) {
+                    self.start_breakpoint = self.breakpoints.last_num();
+                    if !self.run_inferior(&args) {
+                        self.remove_start_breakpoint();
                     }
-                    if let Some(inferior) = Inferior::new(&self.target, &args, &self.breakpoints) {
-                        // Create the inferior
-                        self.inferior = Some(inferior);
-                        // TODO (milestone 1): make the inferior run
-                        // You may use self.inferior.as_mut().unwrap() to get a mutable reference
-                        // to the Inferior object
-                        self.continue_inferior();
+                }
+            }
+            DebuggerCommand::Continue => {
+                if self.inferior.is_none() {
+                    println!("No inferior process running");
+                    return true;
+                }
+                self.continue_inferior();
+            }
+            DebuggerCommand::Backtrace { full, past_main } => {
+                if self.inferior.is_none() {
+                    println!("No inferior process running");
+                    return true;
+                }
+                let bt_res = self.inferior.as_ref().unwrap().print_backtrace(
+                    &self.debug_data,
+                    full,
+                    past_main,
+                );
+                if bt_res.is_err() {
+                    println!("Backtrace failed: {}", bt_res.err().unwrap());
+                }
+            }
+            DebuggerCommand::BreakPoint(target, condition) => {
+                self.add_breakpoint(&target, condition.as_deref(), false);
+            }
+            DebuggerCommand::TemporaryBreakPoint(target, condition) => {
+                self.add_breakpoint(&target, condition.as_deref(), true);
+            }
+            DebuggerCommand::InfoBreakpoints => self.print_breakpoints(),
+            DebuggerCommand::InfoLocals => self.print_frame_variables(false),
+            DebuggerCommand::InfoArgs => self.print_frame_variables(true),
+            DebuggerCommand::InfoRegisters { all, names } => self.print_registers(all, &names),
+            DebuggerCommand::Delete(nums) => {
+                for num in self.breakpoint_nums_or_all(nums) {
+                    if let Some(bp) = self.breakpoints.remove(num) {
+                        self.sync_breakpoint_trap(bp.addr);
+                    } else if let Some(mut wp) = self.breakpoints.remove_watchpoint(num) {
+                        if let Some(inferior) = self.inferior.as_mut() {
+                            wp.remove(inferior).ok();
+                        }
+                        if let Some(scope) = wp.scope {
+                            self.sync_breakpoint_trap(scope.return_addr);
+                        }
                     } else {
-                        println!("Error starting subprocess");
+                        println!("No breakpoint number {}.", num);
                     }
                 }
-                DebuggerCommand::Continue => {
-                    if self.inferior.is_none() {
-                        println!("No inferior process running");
-                        continue;
+            }
+            DebuggerCommand::Disable(nums) => self.set_breakpoints_enabled(nums, false),
+            DebuggerCommand::Enable(nums) => self.set_breakpoints_enabled(nums, true),
+            DebuggerCommand::Commands(num, lines) => {
+                if let Some(num) = self.command_list_breakpoint(num) {
+                    self.breakpoints.get_mut(num).unwrap().commands = lines;
+                }
+            }
+            DebuggerCommand::Ignore(num, count) => match self.breakpoints.get_mut(num) {
+                Some(bp) => {
+                    bp.ignore_count = count;
+                    match count {
+                        0 => println!("Will stop next time breakpoint {} is reached.", num),
+                        1 => println!("Will ignore next crossing of breakpoint {}.", num),
+                        _ => println!(
+                            "Will ignore next {} crossings of breakpoint {}.",
+                            count, num
+                        ),
                     }
-                    self.continue_inferior();
                 }
-                DebuggerCommand::Backtrace => {
-                    if self.inferior.is_none() {
-                        println!("No inferior process running");
-                        continue;
+                None => println!("No breakpoint number {}.", num),
+            },
+            DebuggerCommand::Watch(kind, expression) => self.add_watchpoint(kind, &expression),
+            DebuggerCommand::Quit => {
+                if let Some(inferior) = self.inferior.as_mut() {
+                    inferior.kill();
+                }
+                return false;
+            }
+            DebuggerCommand::Step(count) => {
+                if self.inferior.is_none() {
+                    println!("No inferior process running");
+                    return true;
+                }
+                self.step_lines(count, false);
+            }
+            DebuggerCommand::Next(count) => {
+                if self.inferior.is_none() {
+                    println!("No inferior process running");
+                    return true;
+                }
+                self.step_lines(count, true);
+            }
+            DebuggerCommand::StepInstruction(count) => {
+                if self.inferior.is_none() {
+                    println!("No inferior process running");
+                    return true;
+                }
+                self.step_instructions(count, false);
+            }
+            DebuggerCommand::NextInstruction(count) => {
+                if self.inferior.is_none() {
+                    println!("No inferior process running");
+                    return true;
+                }
+                self.step_instructions(count, true);
+            }
+            DebuggerCommand::Finish => {
+                if self.inferior.is_none() {
+                    println!("No inferior process running");
+                    return true;
+                }
+                self.finish();
+            }
+            DebuggerCommand::Print(text) => {
+                if self.inferior.is_none() {
+                    println!("No inferior process running");
+                    return true;
+                }
+                let inferior = self.inferior.as_ref().unwrap();
+                let value = self.selected_frame().and_then(|frame| {
+                    expr::evaluate(&expr::parse(&text, &self.debug_data)?, &frame)
+                });
+                match value {
+                    Ok(value) if value.optimized_out => println!("{} = <optimized out>", text),
+                    Ok(value) => println!(
+                        "{} = {}",
+                        text,
+                        value::format_value(
+                            &value.entity_type,
+                            &value.bytes,
+                            inferior,
+                            &self.debug_data
+                        )
+                    ),
+                    Err(err) => println!("{}", err),
+                }
+            }
+            DebuggerCommand::Set(text) => {
+                if self.inferior.is_none() {
+                    println!("No inferior process running");
+                    return true;
+                }
+                if let Err(e) = self.set(&text) {
+                    println!("{}", e);
+                }
+            }
+            DebuggerCommand::Examine(spec, addr) => self.examine(&spec, addr.as_deref()),
+            DebuggerCommand::Frame(level) => {
+                let level = level.unwrap_or(self.selected_frame);
+                if let Some(frames) = self.stack() {
+                    self.select_frame(&frames, level);
+                }
+            }
+            DebuggerCommand::Up(count) => {
+                if let Some(frames) = self.stack() {
+                    if self.selected_frame + 1 >= frames.len() {
+                        println!("Initial frame selected; you cannot go up.");
+                    } else {
+                        let level = self.selected_frame.saturating_add(count as usize);
+                        self.select_frame(&frames, level.min(frames.len() - 1));
                     }
-                    let bt_res = self
-                        .inferior
-                        .as_ref()
-                        .unwrap()
-                        .print_backtrace(&self.debug_data);
-                    if bt_res.is_err() {
-                        println!("Backtrace failed: {}", bt_res.err().unwrap());
+                }
+            }
+            DebuggerCommand::Down(count) => {
+                if let Some(frames) = self.stack() {
+                    if self.selected_frame == 0 {
+                        println!("Bottom (innermost) frame selected; you cannot go down.");
+                    } else {
+                        let level = self.selected_frame.saturating_sub(count as usize);
+                        self.select_frame(&frames, level);
                     }
                 }
-                DebuggerCommand::BreakPoint(target) => {
-                    let mut addr_opt: Option<u64> = None;
-                    if target.starts_with('*') {
-                        let addr_str = &target[1..];
-                        addr_opt = parse_address(addr_str);
-                    } else if let Ok(line_num) = target.parse::<u64>() {
-                        if let Some(a) = self.debug_data.get_addr_for_line(None, line_num) {
-                            addr_opt = Some(a);
-                        }
+            }
+        }
+        true
+    }
+
+    /// Starts the target with args, killing the inferior that is running (if any) first.
+    /// Returns false if the target could not be started.
+    fn run_inferior(&mut self, args: &[String]) -> bool {
+        if let Some(inferior) = self.inferior.as_mut() {
+            inferior.kill();
+            self.inferior = None;
+            self.breakpoints.remove_scoped_watchpoints();
+        }
+        if let Some(inferior) = Inferior::new(&self.target, args, &self.breakpoints.enabled_addrs())
+        {
+            // Create the inferior
+            self.inferior = Some(inferior);
+            self.insert_watchpoints();
+            self.continue_inferior();
+            true
+        } else {
+            println!("Error starting subprocess");
+            false
+        }
+    }
+
+    fn remove_start_breakpoint(&mut self) {
+        let num = self.start_breakpoint.take();
+        if let Some(bp) = num.and_then(|num| self.breakpoints.remove(num)) {
+            self.sync_breakpoint_trap(bp.addr);
+        }
+    }
+
+    /// Creates a breakpoint at target (a function name, line number or `*address`), inserting it
+    /// into the running inferior. Returns false if target could not be resolved.
+    fn add_breakpoint(&mut self, target: &str, condition: Option<&str>, temporary: bool) -> bool {
+        let condition = condition.map(|text| Condition::parse(text, &self.debug_data));
+        let condition = match condition {
+            None => None,
+            Some(Ok(condition)) => Some(condition),
+            Some(Err(err)) => {
+                println!("Invalid breakpoint condition: {}", err);
+                return false;
+            }
+        };
+        let mut addr_opt: Option<u64> = None;
+        if let Some(addr_str) = target.strip_prefix('*') {
+            addr_opt = parse_address(addr_str);
+        } else if let Ok(line_num) = target.parse::<u64>() {
+            if let Some(a) = self.debug_data.get_addr_for_line(None, line_num) {
+                addr_opt = Some(a);
+            }
+        } else {
+            if let Some(a) = self.debug_data.get_addr_for_function(None, target) {
+                addr_opt = Some(a);
+            }
+        }
+        if addr_opt.is_none() {
+            eprintln!("Could not resolve breakpoint target {}. ", target);
+            eprintln!(
+                "Usage: {{b | break | breakpoint}} {{*raw address | line number | function name}}"
+            );
+            return false;
+        }
+        let addr = addr_opt.unwrap();
+        let bp = self.breakpoints.add(addr, target, condition, temporary);
+        println!(
+            "Setting {}breakpoint {} at {:#x}",
+            if temporary { "temporary " } else { "" },
+            bp.num,
+            addr
+        );
+        if let Some(inferior) = self.inferior.as_mut() {
+            inferior.set_breakpoint(addr).ok();
+        }
+        true
+    }
+
+    /// Reports the breakpoints the inferior has just stopped at, with how often they have been
+    /// hit, e.g. `Breakpoint 2, func3 (a=100), hit 3 times`, and queues their commands.
+    /// Temporary breakpoints are deleted.
+    fn report_breakpoint_hits(&mut self) {
+        // Commands still queued from an earlier stop are dropped once the inferior has moved on
+        self.pending_commands.clear();
+        let hits = self.breakpoints.take_hits();
+        if hits.is_empty() {
+            return;
+        }
+        let inferior = self.inferior.as_ref().unwrap();
+        let call = match unwind::unwind(inferior, &self.debug_data) {
+            Ok(frames) if !frames.is_empty() => {
+                inferior.describe_call(&frames[0], &self.debug_data)
+            }
+            _ => "??".to_string(),
+        };
+        for num in hits {
+            let bp = match self.breakpoints.iter().find(|bp| bp.num == num) {
+                Some(bp) => bp,
+                None => continue,
+            };
+            self.pending_commands.extend(bp.commands.iter().cloned());
+            if bp.temporary {
+                println!("Temporary breakpoint {}, {}", num, call);
+                let addr = bp.addr;
+                self.breakpoints.remove(num);
+                self.sync_breakpoint_trap(addr);
+            } else {
+                println!(
+                    "Breakpoint {}, {}, hit {} time{}",
+                    num,
+                    call,
+                    bp.hit_count,
+                    if bp.hit_count == 1 { "" } else { "s" }
+                );
+            }
+        }
+    }
+
+    /// Examines memory at the address expression addr, or after the memory examined last.
+    fn examine(&mut self, spec: &Spec, addr: Option<&str>) {
+        let inferior = match self.inferior.as_ref() {
+            Some(inferior) => inferior,
+            None => {
+                println!("No inferior process running");
+                return;
+            }
+        };
+        let addr = match addr {
+            Some(text) => self.selected_frame().and_then(|frame| {
+                let value = expr::evaluate(&expr::parse(text, &self.debug_data)?, &frame)?;
+                expr::to_address(&value, inferior, &self.debug_data)
+            }),
+            None => self
+                .examiner
+                .next
+                .ok_or_else(|| "Argument required (starting display address).".to_string()),
+        };
+        match addr {
+            Ok(addr) => self
+                .examiner
+                .examine(spec, addr, inferior, &self.debug_data),
+            Err(e) => println!("{}", e),
+        }
+    }
+
+    /// Unwinds the inferior's stack up to main, printing why if that is not possible.
+    fn stack(&self) -> Option<Vec<StackFrame>> {
+        let inferior = match self.inferior.as_ref() {
+            Some(inferior) => inferior,
+            None => {
+                println!("No stack.");
+                return None;
+            }
+        };
+        match unwind::unwind(inferior, &self.debug_data) {
+            Ok(mut frames) => {
+                let main = frames.iter().position(|frame| {
+                    self.debug_data
+                        .get_function_containing(frame.pc)
+                        .is_some_and(|function| function.name == "main")
+                });
+                if let Some(main) = main {
+                    frames.truncate(main + 1);
+                }
+                Some(frames)
+            }
+            Err(e) => {
+                println!("Could not unwind the stack: {}", e);
+                None
+            }
+        }
+    }
+
+    /// Selects the frame at level and prints it along with its current source line.
+    fn select_frame(&mut self, frames: &[StackFrame], level: usize) {
+        let frame = match frames.get(level) {
+            Some(frame) => frame,
+            None => {
+                println!("No frame at level {}.", level);
+                return;
+            }
+        };
+        self.selected_frame = level;
+        let inferior = self.inferior.as_ref().unwrap();
+        println!(
+            "{}",
+            inferior.describe_frame(level, frame, &self.debug_data)
+        );
+        if let Some(line) = self.debug_data.get_line_from_addr(frame.pc) {
+            Debugger::print_source_line(&line.file, line.number);
+        }
+    }
+
+    /// Lists the parameters (or, if parameters is false, the local variables) of the function
+    /// executing in the selected frame, with their types and values.
+    fn print_frame_variables(&self, parameters: bool) {
+        let frame = match self.selected_frame() {
+            Ok(frame) => frame,
+            Err(e) => {
+                println!("{}", e);
+                return;
+            }
+        };
+        let function = match self.debug_data.get_function_containing(frame.pc) {
+            Some(function) => function,
+            None => {
+                println!("No symbol table info available.");
+                return;
+            }
+        };
+        let mut vars = function
+            .variables_at(frame.pc)
+            .into_iter()
+            .filter(|var| var.parameter == parameters)
+            .peekable();
+        if vars.peek().is_none() {
+            println!(
+                "{}",
+                if parameters {
+                    "No arguments."
+                } else {
+                    "No locals."
+                }
+            );
+        }
+        for var in vars {
+            println!(
+                "{} {} = {}",
+                var.entity_type.name,
+                var.name,
+                value::format_variable(var, &frame, value::format_bare)
+            );
+        }
+    }
+
+    /// Prints the registers of the selected frame: the general purpose ones, plus the x87, SSE
+    /// and AVX ones if all is set, or just the ones named.
+    fn print_registers(&self, all: bool, names: &[String]) {
+        let frame = match self.selected_frame() {
+            Ok(frame) => frame,
+            Err(_) => {
+                println!("The program has no registers now.");
+                return;
+            }
+        };
+        let mut rows = registers::general_rows(&frame.regs, &self.debug_data);
+        let names: Vec<&str> = names
+            .iter()
+            .map(|name| registers::canonical_name(name.trim_start_matches('$')))
+            .collect();
+        let general = |name: &&str| rows.iter().any(|(row, _)| row == name);
+        if all || !names.iter().all(general) {
+            // The vector registers are not saved in stack frames, so they are always the
+            // innermost frame's
+            let inferior = self.inferior.as_ref().unwrap();
+            match inferior.get_fpregs() {
+                Ok(fpregs) => {
+                    let xstate = inferior.get_xstate().ok();
+                    rows.extend(registers::vector_rows(&fpregs, xstate.as_deref()));
+                }
+                Err(e) => println!("Could not read the floating point registers: {}", e),
+            }
+        }
+        if names.is_empty() {
+            for (_, line) in &rows {
+                println!("{}", line);
+            }
+            return;
+        }
+        for name in names {
+            match rows.iter().find(|(row, _)| row == name) {
+                Some((_, line)) => println!("{}", line),
+                None => println!("Invalid register `{}'", name),
+            }
+        }
+    }
+
+    /// Carries out `set <lvalue> = <expr>`, writing the value to the inferior's memory or
+    /// registers.
+    fn set(&mut self, text: &str) -> Result<(), String> {
+        let (lhs, rhs) = split_assignment(text)
+            .ok_or_else(|| format!("Expected an assignment, as in `set var x = 1`: {}", text))?;
+        if let Some(register) = lhs.strip_prefix('$') {
+            return self.set_register(register, rhs);
+        }
+        let (destination, bytes) = {
+            let frame = self.selected_frame()?;
+            let lhs = expr::parse(lhs, &self.debug_data)?;
+            let rhs = expr::parse(rhs, &self.debug_data)?;
+            expr::assignment(&lhs, &rhs, &frame)?
+        };
+        match destination {
+            Destination::Memory(addr) => self
+                .inferior
+                .as_mut()
+                .unwrap()
+                .write_bytes(addr, &bytes)
+                .map_err(|_| format!("Cannot access memory at address {:#x}", addr)),
+            Destination::Register(register) => {
+                // Callers' registers are saved by their callees, somewhere on the stack
+                if self.selected_frame != 0 {
+                    return Err("Cannot assign to a register variable of an outer frame.".into());
+                }
+                let pid = self.inferior.as_ref().unwrap().pid();
+                let mut regs = ptrace::getregs(pid).map_err(|e| e.to_string())?;
+                if unwind::register_value(&regs, register).is_none() {
+                    return Err(format!("Cannot assign to DWARF register {}.", register));
+                }
+                unwind::set_register_value(&mut regs, register, value::to_word(&bytes));
+                ptrace::setregs(pid, regs).map_err(|e| e.to_string())
+            }
+        }
+    }
+
+    /// Sets a general purpose register of the innermost frame to the value of expression.
+    fn set_register(&mut self, name: &str, expression: &str) -> Result<(), String> {
+        if self.selected_frame != 0 {
+            return Err("Registers can only be set in the innermost frame.".to_string());
+        }
+        let frame = self.selected_frame()?;
+        let value = expr::evaluate(&expr::parse(expression, &self.debug_data)?, &frame)?;
+        let inferior = self.inferior.as_ref().unwrap();
+        let value = expr::to_address(&value, inferior, &self.debug_data)?;
+        let mut regs = frame.regs;
+        if !registers::set_general_value(&mut regs, name, value) {
+            return Err(format!("Invalid register `{}'", name));
+        }
+        ptrace::setregs(inferior.pid(), regs).map_err(|e| e.to_string())
+    }
+
+    /// The selected frame of the running inferior, for resolving variables in.
+    fn selected_frame(&self) -> Result<Frame<'_>, String> {
+        let inferior = self.inferior.as_ref().ok_or("No frame selected.")?;
+        if self.selected_frame == 0 {
+            return Frame::innermost(inferior, &self.debug_data).map_err(|e| e.to_string());
+        }
+        let frames = unwind::unwind(inferior, &self.debug_data).map_err(|e| e.to_string())?;
+        let frame = frames
+            .get(self.selected_frame)
+            .ok_or_else(|| format!("No frame at level {}.", self.selected_frame))?;
+        Ok(Frame {
+            regs: frame.regs,
+            pc: frame.pc,
+            inferior,
+            debug_data: &self.debug_data,
+        })
+    }
+
+    /// Lists all breakpoints with their state, location and hit count.
+    fn print_breakpoints(&self) {
+        if self.breakpoints.is_empty() {
+            println!("No breakpoints.");
+            return;
+        }
+        println!(
+            "{:<5}{:<16}{:<5}{:<5}{:<20}{:<16}Where",
+            "Num", "Type", "Disp", "Enb", "Address", "Location"
+        );
+        for num in self.breakpoints.nums() {
+            if let Some(wp) = self.breakpoints.watchpoints().find(|wp| wp.num == num) {
+                println!(
+                    "{:<5}{:<16}{:<5}{:<5}{:<#20x}{}",
+                    wp.num,
+                    wp.type_name(),
+                    "keep",
+                    if wp.enabled { "y" } else { "n" },
+                    wp.addr,
+                    wp.expression
+                );
+                Debugger::print_hit_count("watchpoint", wp.hit_count);
+                continue;
+            }
+            let bp = match self.breakpoints.iter().find(|bp| bp.num == num) {
+                Some(bp) => bp,
+                None => continue,
+            };
+            print!(
+                "{:<5}{:<16}{:<5}{:<5}{:<#20x}{:<16}",
+                bp.num,
+                "breakpoint",
+                if bp.temporary { "del" } else { "keep" },
+                if bp.enabled { "y" } else { "n" },
+                bp.addr,
+                bp.location
+            );
+            match self.debug_data.get_function_from_addr(bp.addr) {
+                Some(func) => print!("{}", func),
+                None => print!("??"),
+            }
+            match self.debug_data.get_line_from_addr(bp.addr) {
+                Some(line) => println!(" at {}", line),
+                None => println!(),
+            }
+            if let Some(condition) = &bp.condition {
+                println!("{:<5}stop only if {}", "", condition);
+            }
+            if bp.ignore_count > 0 {
+                println!(
+                    "{:<5}Will ignore next {} crossings of breakpoint.",
+                    "", bp.ignore_count
+                );
+            }
+            for line in &bp.commands {
+                println!("{:<8}{}", "", line);
+            }
+            Debugger::print_hit_count("breakpoint", bp.hit_count);
+        }
+    }
+
+    /// Prints the `info breakpoints` line telling how often a breakpoint or watchpoint (`kind`)
+    /// has been hit, if it has been hit at all.
+    fn print_hit_count(kind: &str, hit_count: u64) {
+        if hit_count > 0 {
+            println!(
+                "{:<5}{} already hit {} time{}",
+                "",
+                kind,
+                hit_count,
+                if hit_count == 1 { "" } else { "s" }
+            );
+        }
+    }
+
+    /// Expands an empty breakpoint number list (as in a bare `delete`) to every breakpoint.
+    fn breakpoint_nums_or_all(&self, nums: Vec<usize>) -> Vec<usize> {
+        if nums.is_empty() {
+            self.breakpoints.nums()
+        } else {
+            nums
+        }
+    }
+
+    fn set_breakpoints_enabled(&mut self, nums: Vec<usize>, enabled: bool) {
+        for num in self.breakpoint_nums_or_all(nums) {
+            if let Some(bp) = self.breakpoints.get_mut(num) {
+                bp.enabled = enabled;
+                let addr = bp.addr;
+                self.sync_breakpoint_trap(addr);
+            } else if let Some(wp) = self.breakpoints.get_watchpoint_mut(num) {
+                wp.enabled = enabled;
+                let scope = wp.scope;
+                if let Some(inferior) = self.inferior.as_mut() {
+                    let res = if enabled {
+                        wp.insert(inferior).map(|inserted| {
+                            if let Err(reason) = inserted {
+                                println!("Could not insert watchpoint {}: {}.", num, reason);
+                            }
+                        })
                     } else {
-                        if let Some(a) = self.debug_data.get_addr_for_function(None, &target) {
-                            addr_opt = Some(a);
-                        }
+                        wp.remove(inferior)
+                    };
+                    if let Err(e) = res {
+                        println!("Could not update watchpoint {}: {}", num, e);
                     }
-                    if addr_opt.is_none() {
-                        eprintln!("Could not resolve breakpoint target {}. ", target);
-                        eprintln!("Usage: {{b | break | breakpoint}} {{*raw address | line number | function name}}");
-                        continue;
+                }
+                if let Some(scope) = scope {
+                    self.sync_breakpoint_trap(scope.return_addr);
+                }
+            } else {
+                println!("No breakpoint number {}.", num);
+            }
+        }
+    }
+
+    /// Sets a watchpoint on `expression`, which must be an lvalue such as a variable, `p->x` or
+    /// `arr[2]`.
+    fn add_watchpoint(&mut self, kind: WatchKind, expression: &str) {
+        let frame = match self.inferior {
+            Some(_) => match self.selected_frame() {
+                Ok(frame) => Some(frame),
+                Err(e) => {
+                    println!("{}", e);
+                    return;
+                }
+            },
+            None => None,
+        };
+        // Without a process, only global variables can be resolved
+        let pc = frame.as_ref().map_or(0, |frame| frame.pc);
+        let (addr, entity_type, is_local) = match self.watch_target(expression, frame.as_ref()) {
+            Ok(target) => target,
+            Err(e) => {
+                println!("{}", e);
+                return;
+            }
+        };
+        // Reads cannot be detected by single-stepping, so those need a debug register
+        if kind != WatchKind::Write && !matches!(entity_type.size, 1 | 2 | 4 | 8) {
+            println!("Expression cannot be implemented with read/access watchpoint.");
+            return;
+        }
+        // A local variable is only watched until its frame returns
+        let scope = match (is_local, frame.as_ref()) {
+            (true, Some(frame)) => {
+                let cfa = match self.debug_data.get_cfa(pc, &frame.regs) {
+                    Some(cfa) => cfa,
+                    None => {
+                        println!(
+                            "Cannot watch {}: no call frame information tells when its frame \
+                             returns.",
+                            expression
+                        );
+                        return;
                     }
-                    let addr = addr_opt.unwrap();
-                    println!(
-                        "Setting breakpoint {} at {:#x}",
-                        self.breakpoints.len(),
-                        addr
-                    );
-                    self.breakpoints.push(addr);
-                    if self.inferior.is_some() {
-                        self.inferior.as_mut().unwrap().set_breakpoint(addr).ok();
+                };
+                // The return address is pushed just below the CFA
+                match frame.inferior.read_word(cfa - 8) {
+                    Ok(return_addr) => Some(Scope { cfa, return_addr }),
+                    Err(e) => {
+                        println!("Could not find the frame of {}: {}", expression, e);
+                        return;
                     }
                 }
-                DebuggerCommand::Quit => {
-                    if let Some(inferior) = self.inferior.as_mut() {
-                        inferior.kill();
-                    }
+            }
+            _ => None,
+        };
+        let wp = self
+            .breakpoints
+            .add_watchpoint(kind, expression, addr, entity_type, scope);
+        let num = wp.num;
+        if let Some(inferior) = self.inferior.as_mut() {
+            match wp.insert(inferior) {
+                Ok(Ok(())) => {}
+                Ok(Err(reason)) => {
+                    println!("Could not insert watchpoint {}: {}.", num, reason);
+                    self.breakpoints.remove_watchpoint(num);
                     return;
                 }
-                DebuggerCommand::Step(count) => {
-                    if self.inferior.is_none() {
-                        println!("No inferior process running");
-                        continue;
+                Err(e) => {
+                    println!("Could not set watchpoint on {}: {}", expression, e);
+                    self.breakpoints.remove_watchpoint(num);
+                    return;
+                }
+            }
+        }
+        println!("{} {}: {}", wp.description(), num, expression);
+    }
+
+    /// Finds what `watch expression` watches: the address and type of the lvalue, and whether
+    /// it involves local variables, whose frame limits how long it can be watched. Without a
+    /// process, only a global variable can be watched.
+    fn watch_target(
+        &self,
+        expression: &str,
+        frame: Option<&Frame>,
+    ) -> Result<(u64, Type, bool), String> {
+        let parsed = expr::parse(expression, &self.debug_data)?;
+        let frame = match (&parsed, frame) {
+            (_, Some(frame)) => frame,
+            (Expr::Variable(name), None) => {
+                return match self.debug_data.get_variable_at_addr(0, name) {
+                    Some((var, _)) => match var.location {
+                        Location::Address(addr) => Ok((addr, var.entity_type.clone(), false)),
+                        _ => Err("No frame selected.".to_string()),
+                    },
+                    None => Err(format!("No symbol \"{}\" in current context.", name)),
+                };
+            }
+            (_, None) => {
+                return Err(format!(
+                    "Cannot watch \"{}\" without a running process: only a variable can be \
+                     watched before the program starts.",
+                    expression
+                ))
+            }
+        };
+        let value = expr::evaluate(&parsed, frame)?;
+        if value.optimized_out {
+            return Err(format!("Cannot watch {}: it is optimized out.", expression));
+        }
+        // A local variable counts unless it is static, which lives at a fixed address
+        let is_local = expr::variables(&parsed).into_iter().any(|name| {
+            matches!(
+                self.debug_data.get_variable_at_addr(frame.pc, name),
+                Some((var, Some(_))) if !matches!(var.location, Location::Address(_))
+            )
+        });
+        match value.address {
+            Some(addr) => Ok((addr, value.entity_type, is_local)),
+            None => Err(format!(
+                "Cannot watch {}: it is not stored in memory.",
+                expression
+            )),
+        }
+    }
+
+    /// Inserts all enabled watchpoints into a freshly started inferior.
+    fn insert_watchpoints(&mut self) {
+        let inferior = self.inferior.as_mut().unwrap();
+        for wp in self.breakpoints.watchpoints_mut().filter(|wp| wp.enabled) {
+            match wp.insert(inferior) {
+                Ok(Ok(())) => {}
+                Ok(Err(reason)) => println!("Could not insert watchpoint {}: {}.", wp.num, reason),
+                Err(e) => println!("Could not insert watchpoint {}: {}", wp.num, e),
+            }
+        }
+    }
+
+    /// Makes the running inferior's trap at addr match the breakpoint table: the `0xcc` is
+    /// inserted if some enabled breakpoint is there, and the original byte restored otherwise.
+    fn sync_breakpoint_trap(&mut self, addr: u64) {
+        if let Some(inferior) = self.inferior.as_mut() {
+            let res = if self.breakpoints.is_enabled_at(addr) {
+                inferior.set_breakpoint(addr).map(|_| ())
+            } else {
+                inferior.remove_breakpoint(addr)
+            };
+            if let Err(e) = res {
+                println!("Could not update breakpoint at {:#x}: {}", addr, e);
+            }
+        }
+    }
+
+    /// Steps the inferior forward by `count` machine instructions, treating call instructions as
+    /// a single instruction when `over_calls` is set, and prints the resulting address.
+    fn step_instructions(&mut self, count: u64, over_calls: bool) {
+        let inferior = self.inferior.as_mut().unwrap();
+        let mut status = Status::Exited(0); // Dummy initialization
+        for _ in 0..count {
+            let step_res = match inferior.at_call_instruction() {
+                Ok(true) if over_calls => {
+                    inferior.step_over_call(&mut self.breakpoints, &self.debug_data)
+                }
+                Ok(_) => inferior.step_checked(&mut self.breakpoints, &self.debug_data),
+                Err(e) => Err(e),
+            };
+            status = match step_res {
+                Ok(s) => s,
+                Err(e) => {
+                    eprintln!("Step failed: {}", e);
+                    return;
+                }
+            };
+            match status {
+                Status::Stopped(Signal::SIGTRAP, _) if !self.breakpoints.has_hits() => {}
+                _ => break,
+            }
+        }
+        match status {
+            Status::Stopped(Signal::SIGTRAP, rip) => self.print_instruction_location(rip),
+            _ => self.print_status(&status),
+        }
+    }
+
+    /// Prints an instruction address along with the function and offset it belongs to, and the
+    /// source line if there is one.
+    fn print_instruction_location(&mut self, rip: u64) {
+        self.selected_frame = 0;
+        self.report_breakpoint_hits();
+        print!("Stopped at {:#x}", rip);
+        match self.debug_data.get_symbol_from_addr(rip) {
+            Some((name, offset)) => print!(" <{}+{}>", name, offset),
+            None => print!(" <??>"),
+        }
+        match self.debug_data.get_line_from_addr(rip) {
+            Some(line) => {
+                println!(" ({}:{})", line.file, line.number);
+                Debugger::print_source_line(&line.file, line.number);
+            }
+            None => println!(),
+        }
+    }
+
+    /// Runs the inferior until the current function returns to its caller, then prints the
+    /// value it returned.
+    fn finish(&mut self) {
+        let inferior = self.inferior.as_mut().unwrap();
+        let rip = match ptrace::getregs(inferior.pid()) {
+            Ok(regs) => regs.rip,
+            Err(e) => {
+                eprintln!("Finish failed: {}", e);
+                return;
+            }
+        };
+        let func = match self.debug_data.get_function_containing(rip) {
+            Some(func) => func,
+            None => {
+                println!("Cannot find the function containing {:#x}", rip);
+                return;
+            }
+        };
+        if func.name == "main" {
+            println!("\"finish\" not meaningful in the outermost frame.");
+            return;
+        }
+        let return_type = func.return_type.clone();
+        println!("Run till exit from {}", func.name);
+
+        // The caller's frame holds the return address in rip, and in rsp the CFA of the current
+        // frame, which is where the stack pointer will be once the function has returned
+        let caller = match unwind::unwind(inferior, &self.debug_data) {
+            Ok(frames) if frames.len() > 1 => frames[1].regs,
+            Ok(_) => {
+                println!("\"finish\" not meaningful in the outermost frame.");
+                return;
+            }
+            Err(e) => {
+                eprintln!("Finish failed: {}", e);
+                return;
+            }
+        };
+        let return_addr = caller.rip;
+        let res = inferior.run_until_return(
+            return_addr,
+            caller.rsp,
+            &mut self.breakpoints,
+            &self.debug_data,
+        );
+        let status = match res {
+            Ok(status) => status,
+            Err(e) => {
+                eprintln!("Finish failed: {}", e);
+                return;
+            }
+        };
+        let returned =
+            matches!(status, Status::Stopped(Signal::SIGTRAP, rip) if rip == return_addr);
+        let value = match return_type {
+            Some(ty) if returned => {
+                let inferior = self.inferior.as_ref().unwrap();
+                match return_value_bytes(inferior, &ty) {
+                    Ok(bytes) => Some(value::format_value(&ty, &bytes, inferior, &self.debug_data)),
+                    Err(e) => {
+                        println!("Could not read the return value: {}", e);
+                        None
                     }
+                }
+            }
+            _ => None,
+        };
+        self.print_status(&status);
+        if let Some(value) = value {
+            println!("Value returned is {}", value);
+        }
+    }
 
-                    let mut status = Status::Exited(0); // Dummy initialization
-                    let mut error = None;
-
-                    // Create a scope to borrow self.inferior and self.debug_data
-                    {
-                        let inferior = self.inferior.as_mut().unwrap();
-                        let debug_data = &self.debug_data;
-
-                        // Loop 'count' times (for number of source lines)
-                        'outer: for _ in 0..count {
-                            let regs = ptrace::getregs(inferior.pid()).unwrap();
-                            let start_line = debug_data.get_line_from_addr(regs.rip);
-
-                            // Loop instructions until line changes
-                            loop {
-                                // Step one instruction using your Inferior::step method
-                                let step_res = inferior.step();
-                                match step_res {
-                                    Ok(s) => {
-                                        status = s;
-                                        match status {
-                                            Status::Stopped(signal, rip) => {
-                                                // If stopped by something other than SIGTRAP, stop stepping
-                                                if signal != nix::sys::signal::Signal::SIGTRAP {
-                                                    break 'outer;
-                                                }
-
-                                                let current_line =
-                                                    debug_data.get_line_from_addr(rip);
-
-                                                // Check if we moved to a new line
-                                                if start_line.is_some() && current_line.is_some() {
-                                                    let start = start_line.as_ref().unwrap();
-                                                    let current = current_line.as_ref().unwrap();
-                                                    if start.file != current.file
-                                                        || start.number != current.number
-                                                    {
-                                                        // println!("Line changed, stopping step");
-                                                        break; // Line changed!
-                                                    }
-                                                }
-                                            }
-                                            _ => break 'outer, // Exited or Signaled
-                                        }
+    fn continue_inferior(&mut self) {
+        let continue_res = self
+            .inferior
+            .as_mut()
+            .unwrap()
+            .cont(&mut self.breakpoints, &self.debug_data);
+        if let Ok(status) = continue_res {
+            self.print_status(&status);
+        }
+    }
+
+    /// Steps the inferior forward by `count` source lines. When `over_calls` is set, call
+    /// instructions are executed as a whole (the callee runs until it returns), so stepping stays
+    /// in the current function instead of descending into every callee.
+    fn step_lines(&mut self, count: u64, over_calls: bool) {
+        let mut status = Status::Exited(0); // Dummy initialization
+        let mut error = None;
+
+        // Create a scope to borrow self.inferior and self.debug_data
+        {
+            let inferior = self.inferior.as_mut().unwrap();
+            let debug_data = &self.debug_data;
+            let breakpoints = &mut self.breakpoints;
+
+            // Loop 'count' times (for number of source lines)
+            'outer: for _ in 0..count {
+                let regs = match ptrace::getregs(inferior.pid()) {
+                    Ok(regs) => regs,
+                    Err(e) => {
+                        error = Some(e);
+                        break 'outer;
+                    }
+                };
+                let start_line = debug_data.get_line_from_addr(regs.rip);
+
+                // Loop instructions until line changes
+                loop {
+                    // Whether a single instruction was executed, rather than a whole call
+                    let mut stepped = false;
+                    let step_res = match inferior.at_call_instruction() {
+                        Ok(true) if over_calls => inferior.step_over_call(breakpoints, debug_data),
+                        Ok(_) => {
+                            stepped = true;
+                            inferior.step_checked(breakpoints, debug_data)
+                        }
+                        Err(e) => Err(e),
+                    };
+                    match step_res {
+                        Ok(s) => {
+                            status = s;
+                            match status {
+                                Status::Stopped(signal, rip) => {
+                                    // If stopped by something other than SIGTRAP, stop stepping
+                                    if signal != nix::sys::signal::Signal::SIGTRAP {
+                                        break 'outer;
                                     }
-                                    Err(e) => {
-                                        error = Some(e);
+                                    // A breakpoint or watchpoint ends the step. A single step
+                                    // lands on a breakpoint without executing its trap, and
+                                    // the breakpoint counts even if a watchpoint fired too.
+                                    let landed = stepped
+                                        && inferior.has_breakpoint(rip)
+                                        && breakpoints.should_stop(rip, inferior, debug_data);
+                                    if landed || breakpoints.has_hits() {
                                         break 'outer;
                                     }
-                                }
-                            }
-                        }
-                    }
 
-                    if let Some(e) = error {
-                        eprintln!("Step failed: {}", e);
-                    } else {
-                        self.print_status(&status);
-                    }
-                }
-                DebuggerCommand::Print(var_name) => {
-                    if self.inferior.is_none() {
-                        println!("No inferior process running");
-                        continue;
-                    }
-                    let inferior = self.inferior.as_mut().unwrap();
-                    let pid = inferior.pid();
-                    let regs = ptrace::getregs(pid).unwrap();
-                    let rip = regs.rip;
-
-                    match self.debug_data.get_variable_at_addr(rip, &var_name) {
-                        Some(var) => {
-                            let addr = match var.location {
-                                Location::Address(a) => a,
-                                Location::FramePointerOffset(offset) => {
-                                    // DW_OP_fbreg is relative to the Frame Base (CFA).
-                                    // On x86_64, CFA is typically rbp + 16.
-                                    regs.rbp.wrapping_add(16).wrapping_add(offset as u64)
-                                }
-                            };
-
-                            // Read the value from memory
-                            match ptrace::read(pid, addr as ptrace::AddressType) {
-                                Ok(word) => {
-                                    // Mask based on size (assuming little endian)
-                                    let value = if var.entity_type.size <= 8 {
-                                        let mask = if var.entity_type.size == 8 {
-                                            0xFFFFFFFFFFFFFFFF
-                                        } else {
-                                            (1u64 << (var.entity_type.size * 8)) - 1
-                                        };
-                                        word as u64 & mask
-                                    } else {
-                                        word as u64
-                                    };
-                                    println!("{} = {}", var_name, value);
-                                }
-                                Err(e) => {
-                                    println!("ERROR reading variable memory: {}", e);
+                                    let current_line = debug_data.get_line_from_addr(rip);
+
+                                    // Check if we moved to a new line
+                                    if let (Some(start), Some(current)) =
+                                        (start_line.as_ref(), current_line.as_ref())
+                                    {
+                                        if start.file != current.file
+                                            || start.number != current.number
+                                        {
+                                            break; // Line changed!
+                                        }
+                                    }
                                 }
+                                _ => break 'outer, // Exited or Signaled
                             }
                         }
-                        None => {
-                            println!("Variable '{}' not found in current context", var_name);
+                        Err(e) => {
+                            error = Some(e);
+                            break 'outer;
                         }
                     }
                 }
             }
         }
-    }
 
-    fn continue_inferior(&mut self) {
-        let continue_res = self.inferior.as_mut().unwrap().cont();
-        if continue_res.is_ok() {
-            self.print_status(&continue_res.unwrap());
+        if let Some(e) = error {
+            eprintln!("Step failed: {}", e);
+        } else {
+            self.print_status(&status);
         }
     }
 
     /// This function prompts the user to enter a command, and continues re-prompting until the user
     /// enters a valid command. It uses DebuggerCommand::from_tokens to do the command parsing.
     ///
-    /// You don't need to read, understand, or modify this function.
+    /// A `commands` line is followed by the lines of its command list, which are read here with
+    /// read_command_block up to `end` and returned as part of the command. The list is refused
+    /// before it is read if there is no breakpoint for it to apply to.
     fn get_next_command(&mut self) -> DebuggerCommand {
         loop {
             // Print prompt and get next line of user input
@@ -277,7 +1143,7 @@ impl Debugger {
                     panic!("Unexpected I/O error: {:?}", err);
                 }
                 Ok(line) => {
-                    if line.trim().len() == 0 {
+                    if line.trim().is_empty() {
                         continue;
                     }
                     let _ = self.readline.add_history_entry(line.as_str());
@@ -289,6 +1155,16 @@ impl Debugger {
                     }
                     let tokens: Vec<&str> = line.split_whitespace().collect();
                     if let Some(cmd) = DebuggerCommand::from_tokens(&tokens) {
+                        if let DebuggerCommand::Commands(num, _) = cmd {
+                            // Refuse before the user types the whole list
+                            match self.command_list_breakpoint(num) {
+                                Some(num) => {
+                                    let lines = self.read_command_block();
+                                    return DebuggerCommand::Commands(Some(num), lines);
+                                }
+                                None => continue,
+                            }
+                        }
                         return cmd;
                     } else {
                         println!("Unrecognized command.");
@@ -298,36 +1174,90 @@ impl Debugger {
         }
     }
 
+    /// Works out which breakpoint `commands` applies to: the one numbered num, or like in GDB the
+    /// breakpoint created last. Prints why and returns None if there is no such breakpoint.
+    fn command_list_breakpoint(&self, num: Option<usize>) -> Option<usize> {
+        let num = match num.or_else(|| self.breakpoints.last_num()) {
+            Some(num) => num,
+            None => {
+                println!("No breakpoints specified.");
+                return None;
+            }
+        };
+        if self.breakpoints.iter().any(|bp| bp.num == num) {
+            Some(num)
+        } else if self.breakpoints.watchpoints().any(|wp| wp.num == num) {
+            println!(
+                "Watchpoint {} cannot have commands; only breakpoints can.",
+                num
+            );
+            None
+        } else {
+            println!("No breakpoint number {}.", num);
+            None
+        }
+    }
+
+    /// Reads the command lines that follow `commands`, up to a line saying `end`. Lines that are
+    /// not valid commands are left out.
+    fn read_command_block(&mut self) -> Vec<String> {
+        println!("Type commands for when the breakpoint is hit, one per line.");
+        println!("End with a line saying just \"end\".");
+        let mut lines = Vec::new();
+        loop {
+            let line = match self.readline.readline(">") {
+                Ok(line) => line.trim().to_string(),
+                // Like "end", so that ctrl+c or ctrl+d does not leave a half-entered list
+                Err(ReadlineError::Interrupted) | Err(ReadlineError::Eof) => return lines,
+                Err(err) => panic!("Unexpected I/O error: {:?}", err),
+            };
+            if line == "end" {
+                return lines;
+            }
+            let tokens: Vec<&str> = line.split_whitespace().collect();
+            match tokens.first() {
+                None => {}
+                Some(&"commands") => println!("Command lists cannot be nested."),
+                Some(_) if DebuggerCommand::from_tokens(&tokens).is_some() => lines.push(line),
+                Some(_) => println!("Unrecognized command."),
+            }
+        }
+    }
+
     fn print_status(&mut self, status: &Status) {
         match status {
             Status::Stopped(signal, rip) => {
+                self.selected_frame = 0;
                 println!("Child stopped (signal {:?})", signal);
+                self.report_breakpoint_hits();
                 let debug_current_line = self.debug_data.get_line_from_addr(*rip);
                 let debug_current_func = self.debug_data.get_function_from_addr(*rip);
                 if debug_current_line.is_some() || debug_current_func.is_some() {
                     print!("Stopped at ");
-                    if debug_current_func.is_none() {
-                        print!("<unknown function> ");
-                    } else {
-                        let current_func_name = debug_current_func.unwrap();
-                        print!("{} ", current_func_name);
+                    match debug_current_func {
+                        Some(current_func_name) => print!("{} ", current_func_name),
+                        None => print!("<unknown function> "),
                     }
-                    if debug_current_line.is_none() {
-                        println!("<unknown location>");
-                    } else {
-                        let current_line = debug_current_line.unwrap();
-                        println!("({}:{})", current_line.file, current_line.number);
-                        Debugger::print_source_line(&current_line.file, current_line.number);
+                    match debug_current_line {
+                        Some(current_line) => {
+                            println!("({}:{})", current_line.file, current_line.number);
+                            Debugger::print_source_line(&current_line.file, current_line.number);
+                        }
+                        None => println!("<unknown location>"),
                     }
                 }
             }
             Status::Exited(exit_code) => {
+                self.pending_commands.clear();
                 println!("Child exited (status {})", exit_code);
                 self.inferior = None;
+                self.breakpoints.remove_scoped_watchpoints();
             }
             Status::Signaled(signal) => {
+                self.pending_commands.clear();
                 println!("Child terminated with signal {:?}", signal);
                 self.inferior = None;
+                self.breakpoints.remove_scoped_watchpoints();
             }
         }
     }
@@ -343,11 +1273,84 @@ impl Debugger {
     }
 }
 
+/// Collects the bytes of a function's return value of type ty (System V x86-64 ABI). A `long
+/// double` is returned in `st0`. Other values of up to 16 bytes are returned in registers, one
+/// per 8-byte chunk: chunks holding only floating point data in `xmm0` and `xmm1`, others in
+/// `rax` and `rdx`. Larger values are returned in memory, whose address is left in `rax`.
+fn return_value_bytes(inferior: &Inferior, ty: &Type) -> Result<Vec<u8>, nix::Error> {
+    let regs = ptrace::getregs(inferior.pid())?;
+    let size = ty.size as usize;
+    if size > 16 {
+        return inferior.read_bytes(regs.rax, size);
+    }
+    let fpregs = inferior.get_fpregs()?;
+    let scalar = ty.strip();
+    if scalar.encoding == Some(gimli::DW_ATE_float) && size == 16 {
+        // The 80-bit value, padded to 16 bytes like a `long double` in memory
+        let st0 = fpregs.st_space[..4]
+            .iter()
+            .flat_map(|word| word.to_le_bytes());
+        return Ok(st0.collect());
+    }
+    let mut scalars = Vec::new();
+    collect_scalars(ty, 0, &mut scalars);
+    let (mut int_regs, mut sse_regs) = ([regs.rax, regs.rdx].into_iter(), 0..2);
+    let mut bytes = Vec::with_capacity(16);
+    for chunk in 0..size.div_ceil(8) as u64 {
+        let in_chunk: Vec<bool> = scalars
+            .iter()
+            .filter(|(offset, _)| offset / 8 == chunk)
+            .map(|(_, is_float)| *is_float)
+            .collect();
+        // A chunk of padding alone is not floating point data
+        let word = if !in_chunk.is_empty() && in_chunk.iter().all(|is_float| *is_float) {
+            let xmm = sse_regs.next().unwrap_or(0) * 4;
+            fpregs.xmm_space[xmm] as u64 | ((fpregs.xmm_space[xmm + 1] as u64) << 32)
+        } else {
+            int_regs.next().unwrap_or(0)
+        };
+        bytes.extend_from_slice(&word.to_le_bytes());
+    }
+    bytes.truncate(size);
+    Ok(bytes)
+}
+
+/// Lists the offset of every scalar making up a value of type ty, and whether it is floating
+/// point.
+fn collect_scalars(ty: &Type, offset: u64, scalars: &mut Vec<(u64, bool)>) {
+    match &ty.strip().kind {
+        TypeKind::Struct(members) | TypeKind::Union(members) => {
+            for member in members {
+                collect_scalars(&member.entity_type, offset + member.offset, scalars);
+            }
+        }
+        TypeKind::Array(element, count) => {
+            for index in 0..count.unwrap_or(0) {
+                collect_scalars(element, offset + index * element.size, scalars);
+            }
+        }
+        _ => scalars.push((offset, ty.encoding == Some(gimli::DW_ATE_float))),
+    }
+}
+
+/// Splits `lhs = rhs` at its assignment, which unlike `==`, `<=`, `>=` and `!=` is an `=` on its
+/// own.
+fn split_assignment(text: &str) -> Option<(&str, &str)> {
+    let bytes = text.as_bytes();
+    let index = (0..bytes.len()).find(|&i| {
+        bytes[i] == b'='
+            && bytes.get(i + 1) != Some(&b'=')
+            && (i == 0 || !matches!(bytes[i - 1], b'=' | b'<' | b'>' | b'!'))
+    })?;
+    let (lhs, rhs) = (text[..index].trim(), text[index + 1..].trim());
+    Some((lhs, rhs)).filter(|(lhs, rhs)| !lhs.is_empty() && !rhs.is_empty())
+}
+
 fn parse_address(addr: &str) -> Option<u64> {
     let addr_without_0x = if addr.to_lowercase().starts_with("0x") {
         &addr[2..]
     } else {
-        &addr
+        addr
     };
     u64::from_str_radix(addr_without_0x, 16).ok()
 }
@@ -1,15 +1,57 @@
+use crate::examine::Spec;
+use crate::watchpoint::WatchKind;
+
 pub enum DebuggerCommand {
     Quit,
     Run(Vec<String>),
+    /// `start [args]`: like `run`, but stops at the beginning of main
+    Start(Vec<String>),
     Continue,
-    Backtrace,
-    BreakPoint(String),
+    /// `bt [full] [-past-main]`: whether to also print locals, and whether to continue past main
+    Backtrace {
+        full: bool,
+        past_main: bool,
+    },
+    BreakPoint(String, Option<String>),
+    /// `tbreak <location> [if <cond>]`: a breakpoint that is deleted when it is first hit
+    TemporaryBreakPoint(String, Option<String>),
+    InfoBreakpoints,
+    InfoLocals,
+    InfoArgs,
+    /// `info registers [name...]`, or `info all-registers [name...]` to include the x87, SSE and
+    /// AVX registers
+    InfoRegisters {
+        all: bool,
+        names: Vec<String>,
+    },
+    Delete(Vec<usize>),
+    Disable(Vec<usize>),
+    Enable(Vec<usize>),
+    /// `commands [N]` followed by command lines and `end`: runs the lines whenever breakpoint N
+    /// (by default the last one created) is hit. The lines are read by get_next_command.
+    Commands(Option<usize>, Vec<String>),
+    /// `ignore N count`: do not stop at breakpoint N the next count times it is hit
+    Ignore(usize, u64),
+    Watch(WatchKind, String),
     Step(u64),
+    Next(u64),
+    StepInstruction(u64),
+    NextInstruction(u64),
+    Finish,
     Print(String),
+    /// `set [var] <lvalue> = <expr>`, where lvalue is a variable, something in memory like
+    /// `arr[2]` or `p->x`, or a register like `$rax`
+    Set(String),
+    /// `frame [N]`: selects frame N, or just describes the selected frame
+    Frame(Option<usize>),
+    Up(u64),
+    Down(u64),
+    /// `x/NFU [addr]`: without an address, continues after the memory examined last
+    Examine(Spec, Option<String>),
 }
 
 impl DebuggerCommand {
-    pub fn from_tokens(tokens: &Vec<&str>) -> Option<DebuggerCommand> {
+    pub fn from_tokens(tokens: &[&str]) -> Option<DebuggerCommand> {
         match tokens[0] {
             "q" | "quit" => Some(DebuggerCommand::Quit),
             "r" | "run" => {
@@ -18,36 +60,205 @@ impl DebuggerCommand {
                     args.iter().map(|s| s.to_string()).collect(),
                 ))
             }
+            "start" => Some(DebuggerCommand::Start(
+                tokens[1..].iter().map(|s| s.to_string()).collect(),
+            )),
             "c" | "cont" | "continue" => Some(DebuggerCommand::Continue),
-            "bt" | "back" | "backtrace" => Some(DebuggerCommand::Backtrace),
-            "b" | "break" | "breakpoint" => {
+            "bt" | "back" | "backtrace" => {
+                let mut full = false;
+                let mut past_main = false;
+                for token in &tokens[1..] {
+                    match *token {
+                        "full" | "-full" => full = true,
+                        "-past-main" => past_main = true,
+                        _ => {
+                            println!("No symbol \"{}\" in current context.", token);
+                            return None;
+                        }
+                    }
+                }
+                Some(DebuggerCommand::Backtrace { full, past_main })
+            }
+            "b" | "break" | "breakpoint" | "tb" | "tbreak" => {
                 if tokens.len() < 2 {
                     println!("No breakpoint location given");
                     return None;
                 }
-                Some(DebuggerCommand::BreakPoint(tokens[1].to_string()))
+                // `break <location> if <condition>`
+                let condition = match tokens.get(2) {
+                    Some(&"if") if tokens.len() > 3 => Some(tokens[3..].join(" ")),
+                    Some(&"if") => {
+                        println!("Argument required (boolean expression).");
+                        return None;
+                    }
+                    Some(token) => {
+                        println!("Junk at end of arguments: {}", token);
+                        return None;
+                    }
+                    None => None,
+                };
+                let location = tokens[1].to_string();
+                if tokens[0].starts_with('t') {
+                    Some(DebuggerCommand::TemporaryBreakPoint(location, condition))
+                } else {
+                    Some(DebuggerCommand::BreakPoint(location, condition))
+                }
             }
-            "s" | "step" => {
-                let mut count: u64 = 1;
-                if tokens.len() >= 2 {
-                    if let Ok(c) = tokens[1].parse::<u64>() {
-                        count = c;
-                    } else {
-                        println!("Invalid step count: {}", tokens[1]);
+            "i" | "info" => match tokens.get(1) {
+                Some(&"b") | Some(&"break") | Some(&"breakpoints") => {
+                    Some(DebuggerCommand::InfoBreakpoints)
+                }
+                Some(&"locals") => Some(DebuggerCommand::InfoLocals),
+                Some(&"args") => Some(DebuggerCommand::InfoArgs),
+                Some(&"r") | Some(&"registers") | Some(&"all-registers") => {
+                    Some(DebuggerCommand::InfoRegisters {
+                        all: tokens[1] == "all-registers",
+                        names: tokens[2..].iter().map(|s| s.to_string()).collect(),
+                    })
+                }
+                Some(subcommand) => {
+                    println!("Undefined info command: \"{}\"", subcommand);
+                    None
+                }
+                None => {
+                    println!("\"info\" must be followed by the name of an info command");
+                    None
+                }
+            },
+            "d" | "delete" => Some(DebuggerCommand::Delete(parse_breakpoint_nums(tokens)?)),
+            "disable" => Some(DebuggerCommand::Disable(parse_breakpoint_nums(tokens)?)),
+            "enable" => Some(DebuggerCommand::Enable(parse_breakpoint_nums(tokens)?)),
+            "commands" => match tokens.get(1) {
+                None => Some(DebuggerCommand::Commands(None, Vec::new())),
+                Some(token) => match token.parse::<usize>() {
+                    Ok(num) => Some(DebuggerCommand::Commands(Some(num), Vec::new())),
+                    Err(_) => {
+                        println!("Invalid breakpoint number: {}", token);
+                        None
+                    }
+                },
+            },
+            "ignore" => {
+                let num = match tokens.get(1) {
+                    Some(token) => match token.parse::<usize>() {
+                        Ok(num) => num,
+                        Err(_) => {
+                            println!("Invalid breakpoint number: {}", token);
+                            return None;
+                        }
+                    },
+                    None => {
+                        println!("Argument required (a breakpoint number).");
                         return None;
                     }
+                };
+                if tokens.len() < 3 {
+                    println!("Second argument (specified ignore-count) is missing.");
+                    return None;
+                }
+                Some(DebuggerCommand::Ignore(
+                    num,
+                    parse_count(&tokens[1..], "ignore")?,
+                ))
+            }
+            "watch" | "rwatch" | "awatch" => {
+                if tokens.len() < 2 {
+                    println!("Argument required (expression to compute).");
+                    return None;
                 }
-                Some(DebuggerCommand::Step(count))
+                let kind = match tokens[0] {
+                    "watch" => WatchKind::Write,
+                    "rwatch" => WatchKind::Read,
+                    _ => WatchKind::Access,
+                };
+                Some(DebuggerCommand::Watch(kind, tokens[1..].join(" ")))
             }
+            "s" | "step" => Some(DebuggerCommand::Step(parse_count(tokens, "step")?)),
+            "n" | "next" => Some(DebuggerCommand::Next(parse_count(tokens, "next")?)),
+            "si" | "stepi" => Some(DebuggerCommand::StepInstruction(parse_count(
+                tokens, "stepi",
+            )?)),
+            "ni" | "nexti" => Some(DebuggerCommand::NextInstruction(parse_count(
+                tokens, "nexti",
+            )?)),
+            "fin" | "finish" => Some(DebuggerCommand::Finish),
             "p" | "print" => {
                 if tokens.len() < 2 {
-                    println!("No variable name given");
+                    println!("Argument required (expression to compute).");
+                    return None;
+                }
+                Some(DebuggerCommand::Print(tokens[1..].join(" ")))
+            }
+            "set" => {
+                let assignment = match tokens.get(1) {
+                    Some(&"var") | Some(&"variable") => &tokens[2..],
+                    _ => &tokens[1..],
+                };
+                if assignment.is_empty() {
+                    println!("Argument required (expression to compute).");
                     return None;
                 }
-                Some(DebuggerCommand::Print(tokens[1].to_string()))
+                Some(DebuggerCommand::Set(assignment.join(" ")))
+            }
+            "f" | "frame" => match tokens.get(1) {
+                None => Some(DebuggerCommand::Frame(None)),
+                Some(token) => match token.parse::<usize>() {
+                    Ok(level) => Some(DebuggerCommand::Frame(Some(level))),
+                    Err(_) => {
+                        println!("Invalid frame level: {}", token);
+                        None
+                    }
+                },
+            },
+            token if token == "x" || token.starts_with("x/") => {
+                let spec = match token.strip_prefix("x/") {
+                    Some(spec) => Spec::parse(spec),
+                    None => Spec::parse(""),
+                };
+                let spec = match spec {
+                    Ok(spec) => spec,
+                    Err(e) => {
+                        println!("{}", e);
+                        return None;
+                    }
+                };
+                let addr = Some(tokens[1..].join(" ")).filter(|addr| !addr.is_empty());
+                Some(DebuggerCommand::Examine(spec, addr))
             }
+            "up" => Some(DebuggerCommand::Up(parse_count(tokens, "up")?)),
+            "down" => Some(DebuggerCommand::Down(parse_count(tokens, "down")?)),
             // Default case:
             _ => None,
         }
     }
 }
+
+/// Parses the breakpoint numbers given to commands like `delete`. An empty list means "all
+/// breakpoints".
+fn parse_breakpoint_nums(tokens: &[&str]) -> Option<Vec<usize>> {
+    let mut nums = Vec::new();
+    for token in &tokens[1..] {
+        match token.parse::<usize>() {
+            Ok(num) => nums.push(num),
+            Err(_) => {
+                println!("Invalid breakpoint number: {}", token);
+                return None;
+            }
+        }
+    }
+    Some(nums)
+}
+
+/// Parses the optional repeat count that follows commands like `step`, defaulting to 1.
+fn parse_count(tokens: &[&str], command: &str) -> Option<u64> {
+    match tokens.get(1) {
+        None => Some(1),
+        Some(token) => match token.parse::<u64>() {
+            Ok(count) => Some(count),
+            Err(_) => {
+                println!("Invalid {} count: {}", command, token);
+                None
+            }
+        },
+    }
+}
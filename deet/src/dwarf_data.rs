@@ -1,7 +1,9 @@
 use crate::gimli_wrapper;
+use crate::inferior::Inferior;
+use crate::unwind::CallFrameInfo;
 use addr2line::Context;
-use object::Object;
-use std::convert::TryInto;
+use object::{Object, ObjectSymbol, SymbolKind};
+use std::collections::HashMap;
 use std::{fmt, fs};
 
 #[derive(Debug)]
@@ -12,6 +14,9 @@ pub enum Error {
 
 pub struct DwarfData {
     files: Vec<File>,
+    types: HashMap<TypeId, Type>,
+    symbols: Vec<Symbol>,
+    frame_info: CallFrameInfo,
     addr2line: Context<addr2line::gimli::EndianRcSlice<addr2line::gimli::RunTimeEndian>>,
 }
 
@@ -32,30 +37,78 @@ impl DwarfData {
         let file = fs::File::open(path).or(Err(Error::ErrorOpeningFile))?;
         let mmap = unsafe { memmap2::Mmap::map(&file).or(Err(Error::ErrorOpeningFile))? };
         let object = object::File::parse(&*mmap)
-            .or_else(|e| Err(gimli_wrapper::Error::ObjectError(e.to_string())))?;
+            .map_err(|e| gimli_wrapper::Error::ObjectError(e.to_string()))?;
         let endian = if object.is_little_endian() {
             gimli::RunTimeEndian::Little
         } else {
             gimli::RunTimeEndian::Big
         };
+        let mut symbols: Vec<Symbol> = object
+            .symbols()
+            .filter(|sym| matches!(sym.kind(), SymbolKind::Text | SymbolKind::Data))
+            .filter_map(|sym| {
+                Some(Symbol {
+                    name: sym.name().ok().filter(|name| !name.is_empty())?.to_string(),
+                    address: sym.address(),
+                    size: sym.size(),
+                })
+            })
+            .collect();
+        symbols.sort_by_key(|sym| sym.address);
+        let (files, types) = gimli_wrapper::load_file(&object, endian)?;
         Ok(DwarfData {
-            files: gimli_wrapper::load_file(&object, endian)?,
-            addr2line: Context::new(&object).or_else(|e| Err(gimli_wrapper::Error::from(e)))?,
+            files,
+            types,
+            symbols,
+            frame_info: CallFrameInfo::load(&object, 0),
+            addr2line: Context::new(&object).map_err(gimli_wrapper::Error::from)?,
+        })
+    }
+
+    /// Looks up a type by id, e.g. the target of a pointer.
+    pub fn get_type(&self, id: TypeId) -> Option<&Type> {
+        self.types.get(&id)
+    }
+
+    /// Returns the type a pointer points to, or None for `void *`.
+    pub fn get_pointee<'a>(&'a self, pointee: &'a Pointee) -> Option<&'a Type> {
+        match pointee {
+            Pointee::Void => None,
+            Pointee::Id(id) => self.get_type(*id),
+            Pointee::Type(ty) => Some(ty),
+        }
+    }
+
+    /// Finds a type by its C name, such as `struct node` or a typedef name. Complete
+    /// definitions are preferred over forward declarations of the same struct.
+    pub fn find_type(&self, name: &str) -> Option<&Type> {
+        self.types
+            .values()
+            .filter(|ty| ty.name == name)
+            .max_by_key(|ty| ty.size)
+    }
+
+    /// Finds an enumeration constant by name, returning its enum type and value.
+    pub fn find_enumerator(&self, name: &str) -> Option<(&Type, i64)> {
+        self.types.values().find_map(|ty| match &ty.kind {
+            TypeKind::Enum(enumerators) => enumerators
+                .iter()
+                .find(|e| e.name == name)
+                .map(|e| (ty, e.value)),
+            _ => None,
         })
     }
 
-    #[allow(dead_code)]
     fn get_target_file(&self, file: &str) -> Option<&File> {
         self.files.iter().find(|f| {
             f.name == file || (!file.contains("/") && f.name.ends_with(&format!("/{}", file)))
         })
     }
 
-    #[allow(dead_code)]
     pub fn get_addr_for_line(&self, file: Option<&str>, line_number: u64) -> Option<u64> {
         let target_file = match file {
             Some(filename) => self.get_target_file(filename)?,
-            None => self.files.get(0)?,
+            None => self.files.first()?,
         };
         Some(
             target_file
@@ -66,20 +119,17 @@ impl DwarfData {
         )
     }
 
-    #[allow(dead_code)]
     pub fn get_addr_for_function(&self, file: Option<&str>, func_name: &str) -> Option<u64> {
         match file {
-            Some(filename) => Some(
-                self.get_target_file(filename)?
-                    .functions
-                    .iter()
-                    .find(|func| func.name == func_name)?
-                    .address,
-            ),
+            Some(filename) => {
+                let file = self.get_target_file(filename)?;
+                let func = file.functions.iter().find(|func| func.name == func_name)?;
+                Some(file.skip_prologue(func))
+            }
             None => {
                 for file in &self.files {
                     if let Some(func) = file.functions.iter().find(|func| func.name == func_name) {
-                        return Some(func.address);
+                        return Some(file.skip_prologue(func));
                     }
                 }
                 None
@@ -87,49 +137,102 @@ impl DwarfData {
         }
     }
 
-    #[allow(dead_code)]
+    /// Finds a function defined in the executable by name.
+    pub fn find_function(&self, name: &str) -> Option<&Function> {
+        self.files
+            .iter()
+            .flat_map(|file| file.functions.iter())
+            .find(|func| func.name == name && func.text_length > 0)
+    }
+
     pub fn get_line_from_addr(&self, curr_addr: u64) -> Option<Line> {
-        let location = self
-            .addr2line
-            .find_location(curr_addr)
-            .ok()??;
+        let location = self.addr2line.find_location(curr_addr).ok()??;
         Some(Line {
             file: location.file?.to_string(),
-            number: location.line?.try_into().unwrap(),
+            number: location.line?.into(),
             address: curr_addr,
         })
     }
 
-    #[allow(dead_code)]
+    /// Returns true if addr is the first instruction of a row in the line table, i.e. execution
+    /// stopped at the start of a statement rather than partway through one.
+    pub fn is_line_start(&self, addr: u64) -> bool {
+        match self.addr2line.find_location_range(addr, addr + 1) {
+            Ok(mut ranges) => matches!(ranges.next(), Some((start, _, _)) if start == addr),
+            Err(_) => false,
+        }
+    }
+
     pub fn get_function_from_addr(&self, curr_addr: u64) -> Option<String> {
-        let frame = self
-            .addr2line
-            .find_frames(curr_addr)
-            .ok()?
-            .next()
-            .ok()??;
+        let frame = self.addr2line.find_frames(curr_addr).ok()?.next().ok()??;
         Some(frame.function?.raw_name().ok()?.to_string())
     }
 
-    #[allow(dead_code)]
-    pub fn get_variable_at_addr(&self, addr: u64, name: &str) -> Option<&Variable> {
+    pub fn get_function_containing(&self, addr: u64) -> Option<&Function> {
+        self.files
+            .iter()
+            .flat_map(|file| file.functions.iter())
+            .find(|func| addr >= func.address && addr < func.address + func.text_length)
+    }
+
+    /// Computes the canonical frame address of the frame executing pc from the call frame
+    /// information, given the frame's registers.
+    pub fn get_cfa(&self, pc: u64, regs: &libc::user_regs_struct) -> Option<u64> {
+        self.frame_info.cfa(pc, regs)
+    }
+
+    /// Recovers the registers of the caller of a frame in the executable, see
+    /// CallFrameInfo::caller.
+    pub fn get_caller(
+        &self,
+        pc: u64,
+        regs: &libc::user_regs_struct,
+        inferior: &Inferior,
+    ) -> Option<Option<libc::user_regs_struct>> {
+        self.frame_info.caller(pc, regs, inferior)
+    }
+
+    /// Returns the name of the ELF symbol covering addr, along with addr's offset into it. Unlike
+    /// get_function_from_addr, this also works for code and data without debugging information.
+    pub fn get_symbol_from_addr(&self, addr: u64) -> Option<(&str, u64)> {
+        let index = self.symbols.partition_point(|sym| sym.address <= addr);
+        self.symbols[..index]
+            .iter()
+            .rev()
+            .find(|sym| addr < sym.address + sym.size.max(1))
+            .map(|sym| (sym.name.as_str(), addr - sym.address))
+    }
+
+    /// Finds the variable named name that is visible at addr, preferring the innermost
+    /// declaration. Also returns the function the variable is local to, or None for globals.
+    pub fn get_variable_at_addr(
+        &self,
+        addr: u64,
+        name: &str,
+    ) -> Option<(&Variable, Option<&Function>)> {
         for file in &self.files {
             for func in &file.functions {
                 if addr >= func.address && addr < func.address + func.text_length {
-                    if let Some(var) = func.variables.iter().find(|v| v.name == name) {
-                        println!("Found variable {} ({} {}, located at {}, declared at line {}) in function {}", var.name, var.entity_type.name, var.entity_type.size, var.location, var.line_number, func.name);
-                        return Some(var);
+                    // Blocks nest, so the innermost declaration is the one with the narrowest
+                    // scope. A shadowed variable is declared in an enclosing block.
+                    let var = func
+                        .variables
+                        .iter()
+                        .filter(|v| v.name == name)
+                        .filter_map(|v| Some((v.scope_at(addr)?, v)))
+                        .min_by_key(|(scope, _)| *scope);
+                    if let Some((_, var)) = var {
+                        return Some((var, Some(func)));
                     }
                 }
             }
             if let Some(var) = file.global_variables.iter().find(|v| v.name == name) {
-                return Some(var);
+                return Some((var, None));
             }
         }
         None
     }
 
-    #[allow(dead_code)]
     pub fn print(&self) {
         for file in &self.files {
             println!("------");
@@ -166,32 +269,199 @@ impl DwarfData {
     }
 }
 
+// The offset of a type's DIE in .debug_info, which identifies the type
+pub type TypeId = usize;
+
 #[derive(Debug, Clone, Default)]
 pub struct Type {
-    pub name: String,
+    pub id: Option<TypeId>, // None for types that have no DIE of their own
+    pub name: String,       // Spelled as in C, e.g. `const char *` or `struct point [4]`
     pub size: u64,
+    // DW_AT_encoding of base types, and of the scalar that an enum, pointer, typedef or
+    // qualified type stands for
+    pub encoding: Option<gimli::DwAte>,
+    pub kind: TypeKind,
+}
+
+#[derive(Debug, Clone, Default)]
+pub enum TypeKind {
+    #[default]
+    Base,
+    Void,
+    Pointer(Pointee),
+    Struct(Vec<Member>),
+    Union(Vec<Member>),
+    // Element type and number of elements, which is unknown for e.g. `extern int a[]`
+    Array(Box<Type>, Option<u64>),
+    Enum(Vec<Enumerator>),
+    Typedef(Box<Type>),
+    Const(Box<Type>),
+    Volatile(Box<Type>),
+    // A function, as seen through function pointers
+    Function,
+}
+
+// What a pointer points to. Pointers read from DWARF refer to their target by id, since a
+// struct may point to itself
+#[derive(Debug, Clone)]
+pub enum Pointee {
+    Void,
+    Id(TypeId),
+    Type(Box<Type>),
+}
+
+// A field of a struct or union
+#[derive(Debug, Clone)]
+pub struct Member {
+    pub name: String,
+    pub entity_type: Type,
+    pub offset: u64,           // Byte offset from the start of the struct
+    pub bit_size: Option<u64>, // Width of a bit field
+    pub bit_offset: u64,       // Offset of a bit field's lowest bit within the byte at offset
+}
+
+#[derive(Debug, Clone)]
+pub struct Enumerator {
+    pub name: String,
+    pub value: i64,
 }
 
 impl Type {
-    pub fn new(name: String, size: u64) -> Self {
+    pub fn new(name: String, size: u64, kind: TypeKind) -> Self {
         Type {
-            name: name,
-            size: size,
+            id: None,
+            name,
+            size,
+            encoding: None,
+            kind,
+        }
+    }
+
+    pub fn void() -> Self {
+        Type::new("void".to_string(), 0, TypeKind::Void)
+    }
+
+    /// Returns the type of pointers to this type.
+    pub fn pointer_to(&self) -> Type {
+        let pointee = match (self.id, &self.kind) {
+            (_, TypeKind::Void) => Pointee::Void,
+            (Some(id), _) => Pointee::Id(id),
+            (None, _) => Pointee::Type(Box::new(self.clone())),
+        };
+        let mut pointer = Type::new(pointer_name(&self.name), 8, TypeKind::Pointer(pointee));
+        pointer.encoding = Some(gimli::DW_ATE_address);
+        pointer
+    }
+
+    /// Returns the type this one stands for, looking through typedefs and qualifiers.
+    pub fn strip(&self) -> &Type {
+        match &self.kind {
+            TypeKind::Typedef(target) | TypeKind::Const(target) | TypeKind::Volatile(target) => {
+                target.strip()
+            }
+            _ => self,
+        }
+    }
+
+    /// Interprets the low `size` bytes of raw as an integer of this type, sign-extending signed
+    /// types.
+    pub fn to_i64(&self, raw: u64) -> i64 {
+        let bits = 8 * self.size.clamp(1, 8) as u32;
+        let truncated = if bits == 64 {
+            raw
+        } else {
+            raw & ((1u64 << bits) - 1)
+        };
+        match self.encoding {
+            Some(gimli::DW_ATE_signed) | Some(gimli::DW_ATE_signed_char) => {
+                ((truncated << (64 - bits)) as i64) >> (64 - bits)
+            }
+            _ => truncated as i64,
+        }
+    }
+
+    /// Formats the low `size` bytes of raw as a value of this (scalar) type.
+    pub fn format_scalar(&self, raw: u64) -> String {
+        match self.encoding {
+            Some(gimli::DW_ATE_float) if self.size == 4 => {
+                format!("{}", f32::from_bits(raw as u32))
+            }
+            Some(gimli::DW_ATE_float) => format!("{}", f64::from_bits(raw)),
+            Some(gimli::DW_ATE_boolean) => format!("{}", self.to_i64(raw) != 0),
+            Some(gimli::DW_ATE_address) => format!("{:#x}", self.to_i64(raw) as u64),
+            Some(gimli::DW_ATE_signed) | Some(gimli::DW_ATE_signed_char) => {
+                format!("{}", self.to_i64(raw))
+            }
+            _ => format!("{}", self.to_i64(raw) as u64),
         }
     }
 }
 
+/// Spells the type of pointers to a type named target_name. Pointers to arrays and functions
+/// need parentheses, as in `int (*)[3]`.
+pub fn pointer_name(target_name: &str) -> String {
+    if let Some(index) = target_name.find(" (*") {
+        // Already a pointer to an array or function: `int (*)[3]` becomes `int (**)[3]`
+        return format!(
+            "{}*{}",
+            &target_name[..index + 2],
+            &target_name[index + 2..]
+        );
+    }
+    match target_name.find(" [").or_else(|| target_name.find(" (")) {
+        Some(index) => format!("{} (*){}", &target_name[..index], &target_name[index + 1..]),
+        None if target_name.ends_with('*') => format!("{}*", target_name),
+        None => format!("{} *", target_name),
+    }
+}
+
+/// Where a variable lives.
 #[derive(Clone)]
 pub enum Location {
+    /// A fixed address, as for global variables
     Address(u64),
-    FramePointerOffset(i64),
+    /// A DWARF expression that has to be evaluated against the registers and memory of the
+    /// inferior, as for locals
+    Expression(Expression),
+    /// A location list, as used by optimized code: each entry gives the location for a range of
+    /// pc values. The variable is optimized out outside all of them.
+    List(Vec<LocationListEntry>),
+}
+
+/// The location of a variable while the pc is in `begin..end`.
+#[derive(Clone)]
+pub struct LocationListEntry {
+    pub begin: u64,
+    pub end: u64,
+    pub expression: Expression,
+}
+
+/// A DWARF location expression (a `DW_OP_*` program).
+#[derive(Clone)]
+pub struct Expression {
+    pub bytes: Vec<u8>,
+    pub encoding: gimli::Encoding,
+    /// The operations in readable form, e.g. `DW_OP_fbreg -20`
+    pub text: String,
 }
 
 impl fmt::Display for Location {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        match *self {
+        match self {
             Location::Address(addr) => write!(f, "Address({:#x})", addr),
-            Location::FramePointerOffset(offset) => write!(f, "FramePointerOffset({})", offset),
+            Location::Expression(expression) => write!(f, "Expression({})", expression.text),
+            Location::List(entries) => {
+                let entries: Vec<String> = entries
+                    .iter()
+                    .map(|entry| {
+                        format!(
+                            "{:#x}-{:#x}: {}",
+                            entry.begin, entry.end, entry.expression.text
+                        )
+                    })
+                    .collect();
+                write!(f, "List({})", entries.join(", "))
+            }
         }
     }
 }
@@ -209,6 +479,26 @@ pub struct Variable {
     pub entity_type: Type,
     pub location: Location,
     pub line_number: u64, // Line number in source file
+    /// True for function parameters (DW_TAG_formal_parameter)
+    pub parameter: bool,
+    /// The address ranges of the innermost lexical block declaring the variable, or None if it
+    /// is visible throughout its function
+    pub scope: Option<Vec<(u64, u64)>>,
+}
+
+impl Variable {
+    /// Returns the size of the narrowest scope range containing addr, or None if the variable is
+    /// not visible at addr. Variables visible throughout their function have the widest scope.
+    fn scope_at(&self, addr: u64) -> Option<u64> {
+        match &self.scope {
+            None => Some(u64::MAX),
+            Some(ranges) => ranges
+                .iter()
+                .filter(|(begin, end)| *begin <= addr && addr < *end)
+                .map(|(begin, end)| end - begin)
+                .min(),
+        }
+    }
 }
 
 #[derive(Debug, Default, Clone)]
@@ -217,9 +507,26 @@ pub struct Function {
     pub address: u64,
     pub text_length: u64,
     pub line_number: u64, // Line number in source file
+    pub return_type: Option<Type>,
+    /// What `DW_OP_fbreg` offsets of the function's locals are relative to (DW_AT_frame_base)
+    pub frame_base: Option<Location>,
     pub variables: Vec<Variable>,
 }
 
+impl Function {
+    /// Returns the parameters and locals in scope at addr, innermost block first. Like in GDB,
+    /// shadowed variables are included after the declarations shadowing them.
+    pub fn variables_at(&self, addr: u64) -> Vec<&Variable> {
+        let mut vars: Vec<(u64, &Variable)> = self
+            .variables
+            .iter()
+            .filter_map(|var| Some((var.scope_at(addr)?, var)))
+            .collect();
+        vars.sort_by_key(|(scope, _)| *scope);
+        vars.into_iter().map(|(_, var)| var).collect()
+    }
+}
+
 #[derive(Debug, Default, Clone)]
 pub struct File {
     pub name: String,
@@ -228,6 +535,28 @@ pub struct File {
     pub lines: Vec<Line>,
 }
 
+impl File {
+    /// Returns the address just past func's prologue, so that a breakpoint there sees the
+    /// arguments already stored in the stack frame. Like GDB, this is the address of the second
+    /// line table row inside the function; functions with a single row start at their entry.
+    fn skip_prologue(&self, func: &Function) -> u64 {
+        self.lines
+            .iter()
+            .map(|line| line.address)
+            .filter(|addr| *addr > func.address && *addr < func.address + func.text_length)
+            .min()
+            .unwrap_or(func.address)
+    }
+}
+
+// An entry of the ELF symbol table
+#[derive(Debug, Clone)]
+pub struct Symbol {
+    pub name: String,
+    pub address: u64,
+    pub size: u64,
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub struct Line {
     pub file: String,
@@ -240,5 +569,3 @@ impl fmt::Display for Line {
         write!(f, "{}:{}", self.file, self.number)
     }
 }
-
-
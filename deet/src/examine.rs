@@ -0,0 +1,326 @@
+//! Examines the inferior's memory for the `x` command, like GDB's `x/NFU addr`.
+
+use crate::dwarf_data::DwarfData;
+use crate::inferior::Inferior;
+use crate::value::{self, to_word};
+use iced_x86::{
+    Decoder, DecoderError, DecoderOptions, Formatter, GasFormatter, Instruction, OpKind,
+};
+use nix::sys::ptrace;
+
+// The longest x86-64 instruction, for reading enough code
+const MAX_INSTRUCTION_LEN: u64 = 15;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Hex,
+    Signed,
+    Unsigned,
+    Octal,
+    Binary,
+    Char,
+    String,
+    Instruction,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Unit {
+    Byte,
+    Halfword,
+    Word,
+    Giant,
+}
+
+impl Unit {
+    pub fn size(&self) -> usize {
+        match self {
+            Unit::Byte => 1,
+            Unit::Halfword => 2,
+            Unit::Word => 4,
+            Unit::Giant => 8,
+        }
+    }
+}
+
+/// The `/NFU` part of an `x` command. A format or unit that is left out is the one the previous
+/// `x` used.
+pub struct Spec {
+    pub count: u64,
+    pub format: Option<Format>,
+    pub unit: Option<Unit>,
+}
+
+impl Spec {
+    /// Parses the text after the `/`, e.g. `4xw`: a count followed by format and unit letters in
+    /// any order.
+    pub fn parse(text: &str) -> Result<Spec, String> {
+        let digits = text.len() - text.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+        let count = match &text[..digits] {
+            "" => 1,
+            digits => digits
+                .parse()
+                .map_err(|_| format!("Invalid number \"{}\".", digits))?,
+        };
+        let mut spec = Spec {
+            count,
+            format: None,
+            unit: None,
+        };
+        for letter in text[digits..].chars() {
+            match letter {
+                'x' => spec.format = Some(Format::Hex),
+                'd' => spec.format = Some(Format::Signed),
+                'u' => spec.format = Some(Format::Unsigned),
+                'o' => spec.format = Some(Format::Octal),
+                't' => spec.format = Some(Format::Binary),
+                'c' => spec.format = Some(Format::Char),
+                's' => spec.format = Some(Format::String),
+                'i' => spec.format = Some(Format::Instruction),
+                'b' => spec.unit = Some(Unit::Byte),
+                'h' => spec.unit = Some(Unit::Halfword),
+                'w' => spec.unit = Some(Unit::Word),
+                'g' => spec.unit = Some(Unit::Giant),
+                _ => return Err(format!("Undefined output format \"{}\".", letter)),
+            }
+        }
+        Ok(spec)
+    }
+}
+
+/// What a later `x` defaults to: the format and unit last used, and the address just past the
+/// memory last examined.
+pub struct Examiner {
+    format: Format,
+    unit: Unit,
+    pub next: Option<u64>,
+}
+
+impl Examiner {
+    pub fn new() -> Examiner {
+        Examiner {
+            format: Format::Hex,
+            unit: Unit::Word,
+            next: None,
+        }
+    }
+
+    /// Prints spec.count units of memory starting at addr, in rows labelled with their address.
+    pub fn examine(&mut self, spec: &Spec, addr: u64, inferior: &Inferior, debug_data: &DwarfData) {
+        self.format = spec.format.unwrap_or(self.format);
+        // Characters default to single bytes rather than the last unit, like in GDB
+        self.unit = match (spec.unit, self.format) {
+            (Some(unit), _) => unit,
+            (None, Format::Char) => Unit::Byte,
+            (None, _) => self.unit,
+        };
+        let next = match self.format {
+            Format::String => examine_strings(spec.count, addr, inferior, debug_data),
+            Format::Instruction => examine_instructions(spec.count, addr, inferior, debug_data),
+            format => examine_units(spec.count, addr, format, self.unit, inferior, debug_data),
+        };
+        self.next = Some(next);
+    }
+}
+
+/// Prints count units of memory, returning the address after the last one printed.
+fn examine_units(
+    count: u64,
+    addr: u64,
+    format: Format,
+    unit: Unit,
+    inferior: &Inferior,
+    debug_data: &DwarfData,
+) -> u64 {
+    let size = unit.size();
+    let per_row = match unit {
+        Unit::Giant => 2,
+        Unit::Word => 4,
+        _ => 8,
+    };
+    // All units are read at once, up to the first page that cannot be read
+    let len = count.saturating_mul(size as u64).min(u64::MAX - addr) as usize;
+    let memory = inferior.read_memory_prefix(addr, len);
+    let mut addr = addr;
+    for index in 0..count {
+        if index % per_row == 0 {
+            if index > 0 {
+                println!();
+            }
+            print!("{}:", label(addr, debug_data));
+        }
+        let offset = index as usize * size;
+        let bytes = match memory.get(offset..offset + size) {
+            Some(bytes) => bytes,
+            None => {
+                println!("\tCannot access memory at address {:#x}", addr);
+                return addr;
+            }
+        };
+        print!("\t{}", format_unit(to_word(bytes), format, size));
+        addr += size as u64;
+    }
+    println!();
+    addr
+}
+
+fn format_unit(raw: u64, format: Format, size: usize) -> String {
+    let bits = size * 8;
+    let signed = if bits < 64 {
+        ((raw << (64 - bits)) as i64) >> (64 - bits)
+    } else {
+        raw as i64
+    };
+    match format {
+        Format::Signed => signed.to_string(),
+        Format::Unsigned => raw.to_string(),
+        Format::Octal if raw == 0 => "0".to_string(),
+        Format::Octal => format!("0{:o}", raw),
+        Format::Binary => format!("{:0width$b}", raw, width = bits),
+        Format::Char => format!("{} {}", signed, value::char_literal(raw as u8)),
+        _ => format!("0x{:0width$x}", raw, width = size * 2),
+    }
+}
+
+/// Prints count NUL-terminated strings, one after the other.
+fn examine_strings(count: u64, mut addr: u64, inferior: &Inferior, debug_data: &DwarfData) -> u64 {
+    for _ in 0..count {
+        match value::format_c_string(addr, inferior, debug_data) {
+            Ok((text, len)) => {
+                println!("{}:\t{}", label(addr, debug_data), text);
+                addr += len;
+            }
+            Err(_) => {
+                println!(
+                    "{}:\t<error: Cannot access memory at address {:#x}>",
+                    label(addr, debug_data),
+                    addr
+                );
+                break;
+            }
+        }
+    }
+    addr
+}
+
+/// Prints count instructions, disassembled from the inferior's memory, so that code in shared
+/// libraries or code the program wrote itself can be examined too.
+fn examine_instructions(count: u64, addr: u64, inferior: &Inferior, debug_data: &DwarfData) -> u64 {
+    let len = count
+        .saturating_mul(MAX_INSTRUCTION_LEN)
+        .min(u64::MAX - addr) as usize;
+    let code = inferior.read_memory_prefix(addr, len);
+    let mut decoder = Decoder::with_ip(64, &code, addr, DecoderOptions::NONE);
+    let mut formatter = GasFormatter::new();
+    let options = formatter.options_mut();
+    // Like GDB: `mov    -0x8(%rbp),%rax`
+    options.set_first_operand_char_index(7);
+    options.set_uppercase_hex(false);
+    options.set_branch_leading_zeros(false);
+    options.set_small_hex_numbers_in_decimal(false);
+    let rip = ptrace::getregs(inferior.pid()).map_or(0, |regs| regs.rip);
+    let mut instruction = Instruction::default();
+    let mut addr = addr;
+    for _ in 0..count {
+        // An instruction cut short by unreadable memory cannot be decoded
+        if !decoder.can_decode() {
+            println!("Cannot access memory at address {:#x}", addr);
+            break;
+        }
+        decoder.decode_out(&mut instruction);
+        let mut text = String::new();
+        if instruction.is_invalid() {
+            if decoder.last_error() == DecoderError::NoMoreBytes {
+                println!("Cannot access memory at address {:#x}", addr);
+                break;
+            }
+            text.push_str("(bad)");
+        } else {
+            formatter.format(&instruction, &mut text);
+        }
+        // Calls and jumps also name the function they go to
+        if instruction.op_count() > 0 && instruction.op0_kind() == OpKind::NearBranch64 {
+            if let Some(target) = symbol(instruction.near_branch_target(), debug_data) {
+                text.push_str(&format!(" <{}>", target));
+            }
+        }
+        let marker = if addr == rip { "=> " } else { "   " };
+        println!("{}{}:\t{}", marker, label(addr, debug_data), text);
+        addr = instruction.next_ip();
+    }
+    addr
+}
+
+/// Formats an address with the symbol it lies in, e.g. `0x404030 <arr+8>`.
+fn label(addr: u64, debug_data: &DwarfData) -> String {
+    match symbol(addr, debug_data) {
+        Some(symbol) => format!("{:#x} <{}>", addr, symbol),
+        None => format!("{:#x}", addr),
+    }
+}
+
+/// Names the symbol an address lies in, with the offset into it, e.g. `arr+8`.
+fn symbol(addr: u64, debug_data: &DwarfData) -> Option<String> {
+    match debug_data.get_symbol_from_addr(addr) {
+        Some((name, 0)) if addr != 0 => Some(name.to_string()),
+        Some((name, offset)) if addr != 0 => Some(format!("{}+{}", name, offset)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_spec() {
+        let spec = Spec::parse("").unwrap();
+        assert_eq!((spec.count, spec.format, spec.unit), (1, None, None));
+        let spec = Spec::parse("4xw").unwrap();
+        assert_eq!(
+            (spec.count, spec.format, spec.unit),
+            (4, Some(Format::Hex), Some(Unit::Word))
+        );
+        // Letters may come in any order, and later ones win
+        let spec = Spec::parse("12gdt").unwrap();
+        assert_eq!(
+            (spec.count, spec.format, spec.unit),
+            (12, Some(Format::Binary), Some(Unit::Giant))
+        );
+        let spec = Spec::parse("s").unwrap();
+        assert_eq!(
+            (spec.count, spec.format, spec.unit),
+            (1, Some(Format::String), None)
+        );
+    }
+
+    #[test]
+    fn parse_spec_errors() {
+        assert_eq!(
+            Spec::parse("4q").err().unwrap(),
+            "Undefined output format \"q\"."
+        );
+        assert_eq!(
+            Spec::parse("x4").err().unwrap(),
+            "Undefined output format \"4\"."
+        );
+        assert_eq!(
+            Spec::parse("99999999999999999999x").err().unwrap(),
+            "Invalid number \"99999999999999999999\"."
+        );
+    }
+
+    #[test]
+    fn format_units() {
+        assert_eq!(format_unit(0xff, Format::Hex, 1), "0xff");
+        assert_eq!(format_unit(0x2a, Format::Hex, 4), "0x0000002a");
+        assert_eq!(format_unit(0xff, Format::Signed, 1), "-1");
+        assert_eq!(format_unit(0xffff_ffff, Format::Signed, 4), "-1");
+        assert_eq!(format_unit(u64::MAX, Format::Signed, 8), "-1");
+        assert_eq!(format_unit(0xff, Format::Unsigned, 1), "255");
+        assert_eq!(format_unit(8, Format::Octal, 4), "010");
+        assert_eq!(format_unit(0, Format::Octal, 4), "0");
+        assert_eq!(format_unit(5, Format::Binary, 1), "00000101");
+        assert_eq!(format_unit(0x41, Format::Char, 1), "65 'A'");
+        assert_eq!(format_unit(0xfe, Format::Char, 1), "-2 '\\376'");
+    }
+}
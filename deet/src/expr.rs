@@ -0,0 +1,1625 @@
+//! Parses and evaluates C expressions such as `arr[i] * 2` or `*node->next` against the memory
+//! of the inferior, for `print` and breakpoint conditions.
+
+use crate::dwarf_data::{DwarfData, Function, Type, TypeKind, Variable};
+use crate::inferior::Inferior;
+use crate::location::{self, Frame};
+use crate::registers;
+use crate::value::{self, to_word};
+
+/// A value computed by an expression. Lvalues also remember where they live in the inferior.
+pub struct Value {
+    pub entity_type: Type,
+    pub bytes: Vec<u8>,
+    pub address: Option<u64>,
+    /// Set when the variable has no location at the current pc, so bytes is meaningless
+    pub optimized_out: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Ident(String),
+    // The value and whether the literal had a `u` or `l` suffix
+    Int(u64, bool, bool),
+    Float(f64),
+    Char(u8),
+    Punct(&'static str),
+}
+
+// Longer operators come first so that e.g. `->` is not read as `-`
+const PUNCTUATION: [&str; 30] = [
+    "->", "<<", ">>", "<=", ">=", "==", "!=", "&&", "||", "(", ")", "[", "]", ".", "*", "&", "+",
+    "-", "/", "%", "!", "~", "<", ">", "^", "|", "?", ":", ",", "=",
+];
+
+// Keywords that can start a type name in a cast or `sizeof`
+const TYPE_KEYWORDS: [&str; 16] = [
+    "struct", "union", "enum", "const", "volatile", "unsigned", "signed", "char", "short", "int",
+    "long", "float", "double", "void", "_Bool", "bool",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum UnaryOp {
+    Neg,
+    Plus,
+    Not,
+    BitNot,
+    Deref,
+    AddressOf,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BinaryOp {
+    Mul,
+    Div,
+    Rem,
+    Add,
+    Sub,
+    Shl,
+    Shr,
+    Lt,
+    Gt,
+    Le,
+    Ge,
+    Eq,
+    Ne,
+    BitAnd,
+    BitXor,
+    BitOr,
+    And,
+    Or,
+}
+
+/// A parsed expression.
+#[derive(Debug)]
+pub enum Expr {
+    Int(u64, Type),
+    Float(f64),
+    Char(u8),
+    Variable(String),
+    Member(Box<Expr>, String),
+    // `p->x`, which is `(*p).x`
+    PointerMember(Box<Expr>, String),
+    Index(Box<Expr>, Box<Expr>),
+    Unary(UnaryOp, Box<Expr>),
+    Binary(BinaryOp, Box<Expr>, Box<Expr>),
+    Conditional(Box<Expr>, Box<Expr>, Box<Expr>),
+    Cast(Type, Box<Expr>),
+    SizeOf(Box<Expr>),
+}
+
+/// Parses an expression. Type names are looked up in debug_data, which is needed to tell casts
+/// from parenthesized expressions.
+pub fn parse(text: &str, debug_data: &DwarfData) -> Result<Expr, String> {
+    let tokens = tokenize(text)?;
+    let mut parser = Parser {
+        tokens,
+        pos: 0,
+        debug_data,
+    };
+    let expr = parser.parse_expr()?;
+    match parser.tokens.get(parser.pos) {
+        None => Ok(expr),
+        Some(_) => Err(parser.syntax_error()),
+    }
+}
+
+/// Evaluates an expression in a frame of the inferior, whose variables it may refer to.
+pub fn evaluate(expr: &Expr, frame: &Frame) -> Result<Value, String> {
+    Evaluator {
+        inferior: frame.inferior,
+        debug_data: frame.debug_data,
+        frame: Some(frame),
+    }
+    .evaluate(expr)
+}
+
+/// Where an assignment stores its value.
+pub enum Destination {
+    Memory(u64),
+    /// A variable kept in a register, by DWARF register number
+    Register(u16),
+}
+
+/// Works out the assignment `lhs = rhs` in frame without carrying it out: where lhs lives, and
+/// the value of rhs encoded as the type of lhs.
+pub fn assignment(lhs: &Expr, rhs: &Expr, frame: &Frame) -> Result<(Destination, Vec<u8>), String> {
+    let evaluator = Evaluator {
+        inferior: frame.inferior,
+        debug_data: frame.debug_data,
+        frame: Some(frame),
+    };
+    let target = evaluator.evaluate(lhs)?;
+    if target.optimized_out {
+        return Err("value has been optimized out".to_string());
+    }
+    let value = evaluator.evaluate(rhs)?;
+    let bytes = evaluator.cast(value, &target.entity_type)?.bytes;
+    if let Some(addr) = target.address {
+        return Ok((Destination::Memory(addr), bytes));
+    }
+    // Optimized code may keep a variable in a register, or only know its value
+    let var = match lhs {
+        Expr::Variable(name) => frame.debug_data.get_variable_at_addr(frame.pc, name),
+        _ => None,
+    };
+    let pieces = match var {
+        Some((var, _)) => location::locate(&var.location, frame)?,
+        None => return Err("Left operand of assignment is not an lvalue.".to_string()),
+    };
+    match pieces.as_slice() {
+        [location::Piece {
+            place: location::Place::Register(register),
+            size: None,
+        }] => Ok((Destination::Register(*register), bytes)),
+        pieces
+            if pieces
+                .iter()
+                .any(|piece| matches!(piece.place, location::Place::Value(_))) =>
+        {
+            Err(
+                "Cannot assign to a value computed by DW_OP_stack_value: it is not stored \
+                 anywhere."
+                    .to_string(),
+            )
+        }
+        _ => Err("Left operand of assignment is not an lvalue.".to_string()),
+    }
+}
+
+/// Returns whether a value counts as true in a condition, i.e. is nonzero.
+pub fn is_nonzero(
+    value: &Value,
+    inferior: &Inferior,
+    debug_data: &DwarfData,
+) -> Result<bool, String> {
+    let evaluator = Evaluator {
+        inferior,
+        debug_data,
+        frame: None,
+    };
+    is_true(&evaluator.scalar(value)?)
+}
+
+/// Converts a value to the address it designates, for commands like `x`. Pointers and integers
+/// are used as they are; arrays, structs and functions stand for their own address.
+pub fn to_address(
+    value: &Value,
+    inferior: &Inferior,
+    debug_data: &DwarfData,
+) -> Result<u64, String> {
+    match value.entity_type.strip().kind {
+        TypeKind::Struct(_) | TypeKind::Union(_) | TypeKind::Function => value
+            .address
+            .ok_or_else(|| "Attempt to take address of value not located in memory.".to_string()),
+        _ => {
+            let evaluator = Evaluator {
+                inferior,
+                debug_data,
+                frame: None,
+            };
+            match evaluator.scalar(value)? {
+                Scalar::Int(value, _) => Ok(value as u64),
+                Scalar::Pointer(addr, _) => Ok(addr),
+                Scalar::Float(_) => Err("Invalid number as an address.".to_string()),
+            }
+        }
+    }
+}
+
+/// Reads the value of a variable in frame.
+pub fn read_variable(var: &Variable, frame: &Frame) -> Result<Value, String> {
+    let pieces = location::locate(&var.location, frame)?;
+    if location::is_optimized_out(&pieces) {
+        return Ok(Value {
+            entity_type: var.entity_type.clone(),
+            bytes: vec![0; var.entity_type.size as usize],
+            address: None,
+            optimized_out: true,
+        });
+    }
+    // Optimized code may keep a variable in registers or only know its value
+    let address = location::address(&pieces);
+    let bytes = location::read(&pieces, var.entity_type.size as usize, frame)?;
+    Ok(Value {
+        entity_type: var.entity_type.clone(),
+        bytes,
+        address,
+        optimized_out: false,
+    })
+}
+
+/// Returns the names of the variables an expression refers to.
+pub fn variables(expr: &Expr) -> Vec<&str> {
+    match expr {
+        Expr::Int(..) | Expr::Float(_) | Expr::Char(_) => Vec::new(),
+        Expr::Variable(name) => vec![name.as_str()],
+        Expr::Member(inner, _)
+        | Expr::PointerMember(inner, _)
+        | Expr::Unary(_, inner)
+        | Expr::Cast(_, inner)
+        | Expr::SizeOf(inner) => variables(inner),
+        Expr::Index(lhs, rhs) | Expr::Binary(_, lhs, rhs) => {
+            let mut names = variables(lhs);
+            names.extend(variables(rhs));
+            names
+        }
+        Expr::Conditional(cond, then, otherwise) => {
+            let mut names = variables(cond);
+            names.extend(variables(then));
+            names.extend(variables(otherwise));
+            names
+        }
+    }
+}
+
+fn tokenize(text: &str) -> Result<Vec<Token>, String> {
+    let bytes = text.as_bytes();
+    let mut tokens = Vec::new();
+    let mut pos = 0;
+    while pos < bytes.len() {
+        let c = bytes[pos];
+        if c.is_ascii_whitespace() {
+            pos += 1;
+        } else if c.is_ascii_digit()
+            || (c == b'.' && bytes.get(pos + 1).is_some_and(u8::is_ascii_digit))
+        {
+            let (token, len) = lex_number(&text[pos..])?;
+            tokens.push(token);
+            pos += len;
+        } else if c.is_ascii_alphabetic() || c == b'_' || c == b'$' {
+            // Registers are named with a leading `$`, as in `$rip`
+            let len = 1 + bytes[pos + 1..]
+                .iter()
+                .take_while(|c| c.is_ascii_alphanumeric() || **c == b'_')
+                .count();
+            tokens.push(Token::Ident(text[pos..pos + len].to_string()));
+            pos += len;
+        } else if c == b'\'' {
+            let (value, len) = lex_char(&bytes[pos + 1..])?;
+            tokens.push(Token::Char(value));
+            pos += len + 1;
+        } else {
+            let punct = PUNCTUATION
+                .iter()
+                .find(|p| text[pos..].starts_with(**p))
+                .ok_or_else(|| format!("Invalid character '{}' in expression.", c as char))?;
+            tokens.push(Token::Punct(punct));
+            pos += punct.len();
+        }
+    }
+    Ok(tokens)
+}
+
+fn lex_number(text: &str) -> Result<(Token, usize), String> {
+    // In hex, e is a digit rather than an exponent
+    let is_hex = text.starts_with("0x") || text.starts_with("0X");
+    let len = text
+        .char_indices()
+        .take_while(|(i, c)| {
+            c.is_ascii_alphanumeric()
+                || *c == '.'
+                // An exponent may be signed, as in 1e-5
+                || ((*c == '-' || *c == '+')
+                    && matches!(text.as_bytes()[i - 1], b'e' | b'E')
+                    && !is_hex)
+        })
+        .count();
+    let literal = &text[..len];
+    let invalid = || format!("Invalid number \"{}\".", literal);
+    if !is_hex && (literal.contains('.') || literal.contains(['e', 'E'])) {
+        let value = literal.trim_end_matches(['f', 'F', 'l', 'L']);
+        return Ok((Token::Float(value.parse().map_err(|_| invalid())?), len));
+    }
+    let digits = literal.trim_end_matches(['u', 'U', 'l', 'L']);
+    let suffix = &literal[digits.len()..];
+    let value = if is_hex {
+        u64::from_str_radix(&digits[2..], 16)
+    } else if digits.len() > 1 && digits.starts_with('0') {
+        u64::from_str_radix(&digits[1..], 8)
+    } else {
+        digits.parse()
+    };
+    let value = value.map_err(|_| invalid())?;
+    let unsigned = suffix.contains(['u', 'U']);
+    let long = suffix.contains(['l', 'L']);
+    Ok((Token::Int(value, unsigned, long), len))
+}
+
+// Reads a character literal following its opening quote. Returns the character and the number
+// of bytes read, including the closing quote.
+fn lex_char(bytes: &[u8]) -> Result<(u8, usize), String> {
+    let unmatched = || "Unmatched single quote.".to_string();
+    let (value, len) = match *bytes.first().ok_or_else(unmatched)? {
+        b'\\' => match *bytes.get(1).ok_or_else(unmatched)? {
+            b'n' => (b'\n', 2),
+            b't' => (b'\t', 2),
+            b'r' => (b'\r', 2),
+            b'a' => (0x07, 2),
+            b'b' => (0x08, 2),
+            b'f' => (0x0c, 2),
+            b'v' => (0x0b, 2),
+            b'0'..=b'7' => {
+                let digits = bytes[1..]
+                    .iter()
+                    .take(3)
+                    .take_while(|c| (b'0'..=b'7').contains(*c))
+                    .count();
+                let octal = std::str::from_utf8(&bytes[1..1 + digits]).unwrap();
+                let value = u8::from_str_radix(octal, 8)
+                    .map_err(|_| format!("Octal escape \\{} is out of range.", octal))?;
+                (value, 1 + digits)
+            }
+            b'x' => {
+                let digits = bytes[2..]
+                    .iter()
+                    .take_while(|c| c.is_ascii_hexdigit())
+                    .count();
+                if digits == 0 {
+                    return Err("\\x escape sequence without any hex digits.".to_string());
+                }
+                let hex = std::str::from_utf8(&bytes[2..2 + digits]).unwrap();
+                let value = u8::from_str_radix(hex, 16)
+                    .map_err(|_| format!("Hex escape \\x{} is out of range.", hex))?;
+                (value, 2 + digits)
+            }
+            c => (c, 2),
+        },
+        c => (c, 1),
+    };
+    match bytes.get(len) {
+        Some(b'\'') => Ok((value, len + 1)),
+        _ => Err(unmatched()),
+    }
+}
+
+struct Parser<'a> {
+    tokens: Vec<Token>,
+    pos: usize,
+    debug_data: &'a DwarfData,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn peek_punct(&self, punct: &str) -> bool {
+        matches!(self.peek(), Some(Token::Punct(p)) if *p == punct)
+    }
+
+    fn eat_punct(&mut self, punct: &str) -> bool {
+        if self.peek_punct(punct) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect_punct(&mut self, punct: &str) -> Result<(), String> {
+        if self.eat_punct(punct) {
+            Ok(())
+        } else {
+            Err(self.syntax_error())
+        }
+    }
+
+    fn syntax_error(&self) -> String {
+        let rest: Vec<String> = self.tokens[self.pos.min(self.tokens.len())..]
+            .iter()
+            .map(token_text)
+            .collect();
+        if rest.is_empty() {
+            "A syntax error in expression, near `'.".to_string()
+        } else {
+            format!("A syntax error in expression, near `{}'.", rest.join(" "))
+        }
+    }
+
+    fn parse_expr(&mut self) -> Result<Expr, String> {
+        let condition = self.parse_binary(0)?;
+        if !self.eat_punct("?") {
+            return Ok(condition);
+        }
+        let then = self.parse_expr()?;
+        self.expect_punct(":")?;
+        let otherwise = self.parse_expr()?;
+        Ok(Expr::Conditional(
+            Box::new(condition),
+            Box::new(then),
+            Box::new(otherwise),
+        ))
+    }
+
+    // Parses binary operators by precedence climbing, from `||` (level 0) to `*` (level 9)
+    fn parse_binary(&mut self, level: usize) -> Result<Expr, String> {
+        const LEVELS: [&[(&str, BinaryOp)]; 10] = [
+            &[("||", BinaryOp::Or)],
+            &[("&&", BinaryOp::And)],
+            &[("|", BinaryOp::BitOr)],
+            &[("^", BinaryOp::BitXor)],
+            &[("&", BinaryOp::BitAnd)],
+            &[("==", BinaryOp::Eq), ("!=", BinaryOp::Ne)],
+            &[
+                ("<", BinaryOp::Lt),
+                (">", BinaryOp::Gt),
+                ("<=", BinaryOp::Le),
+                (">=", BinaryOp::Ge),
+            ],
+            &[("<<", BinaryOp::Shl), (">>", BinaryOp::Shr)],
+            &[("+", BinaryOp::Add), ("-", BinaryOp::Sub)],
+            &[
+                ("*", BinaryOp::Mul),
+                ("/", BinaryOp::Div),
+                ("%", BinaryOp::Rem),
+            ],
+        ];
+        if level == LEVELS.len() {
+            return self.parse_unary();
+        }
+        let mut lhs = self.parse_binary(level + 1)?;
+        'operators: loop {
+            for (punct, op) in LEVELS[level] {
+                if self.eat_punct(punct) {
+                    let rhs = self.parse_binary(level + 1)?;
+                    lhs = Expr::Binary(*op, Box::new(lhs), Box::new(rhs));
+                    continue 'operators;
+                }
+            }
+            return Ok(lhs);
+        }
+    }
+
+    fn parse_unary(&mut self) -> Result<Expr, String> {
+        const OPERATORS: [(&str, UnaryOp); 6] = [
+            ("-", UnaryOp::Neg),
+            ("+", UnaryOp::Plus),
+            ("!", UnaryOp::Not),
+            ("~", UnaryOp::BitNot),
+            ("*", UnaryOp::Deref),
+            ("&", UnaryOp::AddressOf),
+        ];
+        for (punct, op) in OPERATORS {
+            if self.eat_punct(punct) {
+                return Ok(Expr::Unary(op, Box::new(self.parse_unary()?)));
+            }
+        }
+        if matches!(self.peek(), Some(Token::Ident(name)) if name == "sizeof") {
+            self.pos += 1;
+            if self.peek_punct("(") && self.starts_type_name(self.pos + 1) {
+                self.pos += 1;
+                let ty = self.parse_type_name()?;
+                self.expect_punct(")")?;
+                return Ok(Expr::Int(ty.size, unsigned_long()));
+            }
+            return Ok(Expr::SizeOf(Box::new(self.parse_unary()?)));
+        }
+        if self.peek_punct("(") && self.starts_type_name(self.pos + 1) {
+            self.pos += 1;
+            let ty = self.parse_type_name()?;
+            self.expect_punct(")")?;
+            return Ok(Expr::Cast(ty, Box::new(self.parse_unary()?)));
+        }
+        self.parse_postfix()
+    }
+
+    fn parse_postfix(&mut self) -> Result<Expr, String> {
+        let mut expr = self.parse_primary()?;
+        loop {
+            if self.eat_punct("[") {
+                let index = self.parse_expr()?;
+                self.expect_punct("]")?;
+                expr = Expr::Index(Box::new(expr), Box::new(index));
+            } else if self.eat_punct(".") {
+                expr = Expr::Member(Box::new(expr), self.parse_ident()?);
+            } else if self.eat_punct("->") {
+                expr = Expr::PointerMember(Box::new(expr), self.parse_ident()?);
+            } else {
+                return Ok(expr);
+            }
+        }
+    }
+
+    fn parse_primary(&mut self) -> Result<Expr, String> {
+        let token = match self.peek() {
+            Some(token) => token.clone(),
+            None => return Err(self.syntax_error()),
+        };
+        self.pos += 1;
+        match token {
+            Token::Int(value, unsigned, long) => {
+                Ok(Expr::Int(value, literal_type(value, unsigned, long)))
+            }
+            Token::Float(value) => Ok(Expr::Float(value)),
+            Token::Char(value) => Ok(Expr::Char(value)),
+            Token::Ident(name) => Ok(Expr::Variable(name)),
+            Token::Punct("(") => {
+                let expr = self.parse_expr()?;
+                self.expect_punct(")")?;
+                Ok(expr)
+            }
+            Token::Punct(_) => {
+                self.pos -= 1;
+                Err(self.syntax_error())
+            }
+        }
+    }
+
+    fn parse_ident(&mut self) -> Result<String, String> {
+        match self.peek() {
+            Some(Token::Ident(name)) => {
+                let name = name.clone();
+                self.pos += 1;
+                Ok(name)
+            }
+            _ => Err(self.syntax_error()),
+        }
+    }
+
+    // A type name starts with a type keyword or the name of a typedef
+    fn starts_type_name(&self, pos: usize) -> bool {
+        match self.tokens.get(pos) {
+            Some(Token::Ident(name)) => {
+                TYPE_KEYWORDS.contains(&name.as_str())
+                    || self
+                        .debug_data
+                        .find_type(name)
+                        .is_some_and(|ty| matches!(ty.kind, TypeKind::Typedef(_)))
+            }
+            _ => false,
+        }
+    }
+
+    // Parses a type name such as `unsigned long`, `struct node *` or `const char *`
+    fn parse_type_name(&mut self) -> Result<Type, String> {
+        let mut words = Vec::new();
+        while let Some(Token::Ident(word)) = self.peek() {
+            if matches!(word.as_str(), "const" | "volatile") {
+                // Qualifiers do not change how values are read
+                self.pos += 1;
+                continue;
+            }
+            if !words.is_empty() && !is_base_type_word(word) {
+                break;
+            }
+            let word = word.clone();
+            self.pos += 1;
+            let tagged = matches!(word.as_str(), "struct" | "union" | "enum");
+            words.push(word);
+            if tagged {
+                words.push(self.parse_ident()?);
+                break;
+            }
+            if !is_base_type_word(&words[0]) {
+                // A typedef name stands alone
+                break;
+            }
+        }
+        let mut ty = match words.first().map(String::as_str) {
+            // Only qualifiers, as in `(const)1`
+            None => return Err("A type name is required.".to_string()),
+            Some("struct" | "union" | "enum") => {
+                let name = words.join(" ");
+                self.debug_data
+                    .find_type(&name)
+                    .cloned()
+                    .ok_or_else(|| match &words[..] {
+                        [tag, tag_name] => format!("No {} type named {}.", tag, tag_name),
+                        _ => format!("No type named {}.", name),
+                    })?
+            }
+            Some(word) if !is_base_type_word(word) => self
+                .debug_data
+                .find_type(word)
+                .cloned()
+                .ok_or_else(|| format!("No symbol \"{}\" in current context.", word))?,
+            _ => base_type_from_words(&words)?,
+        };
+        while self.eat_punct("*") {
+            ty = ty.pointer_to();
+        }
+        Ok(ty)
+    }
+}
+
+fn token_text(token: &Token) -> String {
+    match token {
+        Token::Ident(name) => name.clone(),
+        Token::Int(value, _, _) => value.to_string(),
+        Token::Float(value) => value.to_string(),
+        Token::Char(value) => format!("'{}'", *value as char),
+        Token::Punct(punct) => punct.to_string(),
+    }
+}
+
+fn is_base_type_word(word: &str) -> bool {
+    matches!(
+        word,
+        "unsigned"
+            | "signed"
+            | "char"
+            | "short"
+            | "int"
+            | "long"
+            | "float"
+            | "double"
+            | "void"
+            | "_Bool"
+            | "bool"
+    )
+}
+
+/// Builds the x86-64 base type spelled by words such as `unsigned long`, named the way GCC
+/// names it in DWARF (`long unsigned int`).
+fn base_type_from_words(words: &[String]) -> Result<Type, String> {
+    let count = |word: &str| words.iter().filter(|w| *w == word).count();
+    let unsigned = count("unsigned") > 0;
+    let (longs, short) = (count("long"), count("short") > 0);
+    let ty = if count("void") > 0 {
+        Type::void()
+    } else if count("_Bool") > 0 || count("bool") > 0 {
+        base_type("_Bool", 1, gimli::DW_ATE_boolean)
+    } else if count("float") > 0 {
+        base_type("float", 4, gimli::DW_ATE_float)
+    } else if count("double") > 0 && longs > 0 {
+        base_type("long double", 16, gimli::DW_ATE_float)
+    } else if count("double") > 0 {
+        base_type("double", 8, gimli::DW_ATE_float)
+    } else if count("char") > 0 {
+        match (unsigned, count("signed") > 0) {
+            (true, _) => base_type("unsigned char", 1, gimli::DW_ATE_unsigned_char),
+            (false, true) => base_type("signed char", 1, gimli::DW_ATE_signed_char),
+            (false, false) => base_type("char", 1, gimli::DW_ATE_signed_char),
+        }
+    } else {
+        let (name, size) = match (short, longs) {
+            (true, _) => ("short", 2),
+            (false, 0) => ("", 4),
+            (false, 1) => ("long", 8),
+            (false, _) => ("long long", 8),
+        };
+        match (unsigned, name) {
+            (true, "") => base_type("unsigned int", size, gimli::DW_ATE_unsigned),
+            (true, _) => base_type(
+                &format!("{} unsigned int", name),
+                size,
+                gimli::DW_ATE_unsigned,
+            ),
+            (false, "") => base_type("int", size, gimli::DW_ATE_signed),
+            (false, _) => base_type(&format!("{} int", name), size, gimli::DW_ATE_signed),
+        }
+    };
+    if words.iter().any(|w| !is_base_type_word(w)) {
+        return Err(format!("Invalid type name \"{}\".", words.join(" ")));
+    }
+    Ok(ty)
+}
+
+fn base_type(name: &str, size: u64, encoding: gimli::DwAte) -> Type {
+    let mut ty = Type::new(name.to_string(), size, TypeKind::Base);
+    ty.encoding = Some(encoding);
+    ty
+}
+
+fn int() -> Type {
+    base_type("int", 4, gimli::DW_ATE_signed)
+}
+
+fn long() -> Type {
+    base_type("long", 8, gimli::DW_ATE_signed)
+}
+
+fn unsigned_long() -> Type {
+    base_type("unsigned long", 8, gimli::DW_ATE_unsigned)
+}
+
+fn double() -> Type {
+    base_type("double", 8, gimli::DW_ATE_float)
+}
+
+// Integer literals are `int` if they fit, and `long` otherwise
+fn literal_type(value: u64, unsigned: bool, long: bool) -> Type {
+    let wide = long || value > i32::MAX as u64;
+    match (unsigned, wide) {
+        (false, false) => int(),
+        (false, true) => self::long(),
+        (true, false) => base_type("unsigned int", 4, gimli::DW_ATE_unsigned),
+        (true, true) => unsigned_long(),
+    }
+}
+
+// The operands of arithmetic, after reading them according to their type
+enum Scalar {
+    Int(i64, Type),
+    Float(f64),
+    // The address and the type pointed to
+    Pointer(u64, Type),
+}
+
+struct Evaluator<'a> {
+    inferior: &'a Inferior,
+    debug_data: &'a DwarfData,
+    /// The frame variables are looked up in, if any
+    frame: Option<&'a Frame<'a>>,
+}
+
+impl Evaluator<'_> {
+    fn evaluate(&self, expr: &Expr) -> Result<Value, String> {
+        match expr {
+            Expr::Int(value, ty) => Ok(Value::from_i64(*value as i64, ty.clone())),
+            Expr::Float(value) => Ok(Value::from_f64(*value, double())),
+            Expr::Char(value) => Ok(Value::from_i64(
+                *value as i64,
+                base_type("char", 1, gimli::DW_ATE_signed_char),
+            )),
+            Expr::Variable(name) => self.variable(name),
+            Expr::Member(base, name) => {
+                let base = self.evaluate(base)?;
+                // Like GDB, `.` also works on pointers to structs
+                let base = match base.entity_type.strip().kind {
+                    TypeKind::Pointer(_) => self.dereference(base)?,
+                    _ => base,
+                };
+                member(base, name)
+            }
+            Expr::PointerMember(base, name) => {
+                let base = self.evaluate(base)?;
+                member(self.dereference(base)?, name)
+            }
+            Expr::Index(base, index) => {
+                let base = self.evaluate(base)?;
+                let index = self.evaluate(index)?;
+                self.index(base, index)
+            }
+            Expr::Unary(op, operand) => {
+                let operand = self.evaluate(operand)?;
+                self.unary(*op, operand)
+            }
+            Expr::Binary(BinaryOp::And, lhs, rhs) => {
+                let result = self.truth(lhs)? && self.truth(rhs)?;
+                Ok(Value::from_i64(result as i64, int()))
+            }
+            Expr::Binary(BinaryOp::Or, lhs, rhs) => {
+                let result = self.truth(lhs)? || self.truth(rhs)?;
+                Ok(Value::from_i64(result as i64, int()))
+            }
+            Expr::Binary(op, lhs, rhs) => {
+                let lhs = self.evaluate(lhs)?;
+                let rhs = self.evaluate(rhs)?;
+                self.binary(*op, lhs, rhs)
+            }
+            Expr::Conditional(condition, then, otherwise) => {
+                if self.truth(condition)? {
+                    self.evaluate(then)
+                } else {
+                    self.evaluate(otherwise)
+                }
+            }
+            Expr::Cast(ty, operand) => {
+                let operand = self.evaluate(operand)?;
+                self.cast(operand, ty)
+            }
+            Expr::SizeOf(operand) => {
+                let ty = self.static_type(operand)?;
+                Ok(Value::from_i64(ty.size as i64, unsigned_long()))
+            }
+        }
+    }
+
+    /// Works out the type of expr without evaluating it, as for the operand of `sizeof`, which
+    /// must not read the inferior's memory (`sizeof(*p)` is fine even if p is a bad pointer).
+    fn static_type(&self, expr: &Expr) -> Result<Type, String> {
+        let not_a_number =
+            || "Argument to arithmetic operation not a number or boolean.".to_string();
+        Ok(match expr {
+            Expr::Int(_, ty) => ty.clone(),
+            Expr::Float(_) => double(),
+            Expr::Char(_) => base_type("char", 1, gimli::DW_ATE_signed_char),
+            Expr::Variable(name) => self.variable_type(name)?,
+            Expr::Member(base, name) => {
+                let base = self.static_type(base)?;
+                // Like GDB, `.` also works on pointers to structs
+                let base = match (&base.strip().kind, self.target_type(&base)) {
+                    (TypeKind::Pointer(_), Some(target)) => target,
+                    _ => base,
+                };
+                member_type(&base, name)?
+            }
+            Expr::PointerMember(base, name) => {
+                let base = self.static_type(base)?;
+                let target = self.target_type(&base).ok_or_else(|| {
+                    "The -> operator can only be applied to pointer values.".to_string()
+                })?;
+                member_type(&target, name)?
+            }
+            Expr::Index(base, _) => {
+                self.target_type(&self.static_type(base)?).ok_or_else(|| {
+                    "cannot subscript something of a non-array, non-pointer type".to_string()
+                })?
+            }
+            Expr::Unary(UnaryOp::Deref, operand) => {
+                match self.target_type(&self.static_type(operand)?) {
+                    Some(target) if !matches!(target.strip().kind, TypeKind::Void) => target,
+                    _ => return Err("Attempt to take contents of a non-pointer value.".to_string()),
+                }
+            }
+            Expr::Unary(UnaryOp::AddressOf, operand) => self.static_type(operand)?.pointer_to(),
+            Expr::Unary(UnaryOp::Not, _) => int(),
+            Expr::Unary(_, operand) => {
+                let ty = self.static_type(operand)?.strip().clone();
+                match ty.kind {
+                    TypeKind::Base | TypeKind::Enum(_) if is_float(&ty) => double(),
+                    TypeKind::Base | TypeKind::Enum(_) => promote(ty),
+                    _ => return Err(not_a_number()),
+                }
+            }
+            Expr::Binary(op, lhs, rhs) => {
+                let is_logical = matches!(
+                    op,
+                    BinaryOp::Lt
+                        | BinaryOp::Gt
+                        | BinaryOp::Le
+                        | BinaryOp::Ge
+                        | BinaryOp::Eq
+                        | BinaryOp::Ne
+                        | BinaryOp::And
+                        | BinaryOp::Or
+                );
+                if is_logical {
+                    return Ok(int());
+                }
+                let lhs = self.static_type(lhs)?.strip().clone();
+                let rhs = self.static_type(rhs)?.strip().clone();
+                match (self.target_type(&lhs), self.target_type(&rhs)) {
+                    (Some(_), Some(_)) if *op == BinaryOp::Sub => long(),
+                    (Some(target), None) if matches!(op, BinaryOp::Add | BinaryOp::Sub) => {
+                        target.pointer_to()
+                    }
+                    (None, Some(target)) if *op == BinaryOp::Add => target.pointer_to(),
+                    (Some(_), _) | (_, Some(_)) => return Err(not_a_number()),
+                    _ if is_float(&lhs) || is_float(&rhs) => double(),
+                    _ if matches!(op, BinaryOp::Shl | BinaryOp::Shr) => promote(lhs),
+                    _ => arithmetic_type(lhs, rhs),
+                }
+            }
+            Expr::Conditional(_, then, _) => self.static_type(then)?,
+            Expr::Cast(ty, _) => ty.clone(),
+            Expr::SizeOf(_) => unsigned_long(),
+        })
+    }
+
+    /// Looks up the type of a variable like variable(), without reading it.
+    fn variable_type(&self, name: &str) -> Result<Type, String> {
+        if let Some(register) = name.strip_prefix('$') {
+            return Ok(self.register(register)?.entity_type);
+        }
+        if let Some(frame) = self.frame {
+            if let Some((var, _)) = self.debug_data.get_variable_at_addr(frame.pc, name) {
+                return Ok(var.entity_type.clone());
+            }
+        }
+        if let Some((ty, _)) = self.debug_data.find_enumerator(name) {
+            return Ok(ty.clone());
+        }
+        if let Some(function) = self.debug_data.find_function(name) {
+            return Ok(function_value(function).entity_type);
+        }
+        Err(format!("No symbol \"{}\" in current context.", name))
+    }
+
+    /// Returns the type a pointer points to or an array holds, or None for other types.
+    fn target_type(&self, ty: &Type) -> Option<Type> {
+        match &ty.strip().kind {
+            TypeKind::Pointer(pointee) => Some(
+                self.debug_data
+                    .get_pointee(pointee)
+                    .cloned()
+                    .unwrap_or_else(Type::void),
+            ),
+            TypeKind::Array(element, _) => Some((**element).clone()),
+            _ => None,
+        }
+    }
+
+    fn truth(&self, expr: &Expr) -> Result<bool, String> {
+        let value = self.evaluate(expr)?;
+        is_true(&self.scalar(&value)?)
+    }
+
+    fn variable(&self, name: &str) -> Result<Value, String> {
+        if let Some(register) = name.strip_prefix('$') {
+            return self.register(register);
+        }
+        if let Some(frame) = self.frame {
+            if let Some((var, _)) = self.debug_data.get_variable_at_addr(frame.pc, name) {
+                return read_variable(var, frame);
+            }
+        }
+        if let Some((ty, value)) = self.debug_data.find_enumerator(name) {
+            return Ok(Value::from_i64(value, ty.clone()));
+        }
+        if let Some(function) = self.debug_data.find_function(name) {
+            return Ok(function_value(function));
+        }
+        Err(format!("No symbol \"{}\" in current context.", name))
+    }
+
+    /// Reads a register as it was in the frame, typed like GDB types it.
+    fn register(&self, name: &str) -> Result<Value, String> {
+        let frame = self.frame.ok_or_else(|| "No registers.".to_string())?;
+        let value = registers::general_value(&frame.regs, name)
+            .ok_or_else(|| format!("Invalid register `{}'", name))?;
+        let entity_type = match registers::canonical_name(name) {
+            "rip" => Type::new("void ()".to_string(), 1, TypeKind::Function).pointer_to(),
+            "rsp" | "rbp" => Type::void().pointer_to(),
+            "eflags" | "cs" | "ss" | "ds" | "es" | "fs" | "gs" => int(),
+            _ => long(),
+        };
+        Ok(Value::from_i64(value as i64, entity_type))
+    }
+
+    fn read(&self, addr: u64, entity_type: Type) -> Result<Value, String> {
+        let bytes = self
+            .inferior
+            .read_bytes(addr, entity_type.size as usize)
+            .map_err(|_| format!("Cannot access memory at address {:#x}", addr))?;
+        Ok(Value {
+            entity_type,
+            bytes,
+            address: Some(addr),
+            optimized_out: false,
+        })
+    }
+
+    fn dereference(&self, value: Value) -> Result<Value, String> {
+        match self.scalar(&value)? {
+            // A function is shown by its address rather than by its code
+            Scalar::Pointer(addr, target) if matches!(target.strip().kind, TypeKind::Function) => {
+                Ok(Value {
+                    entity_type: target,
+                    bytes: addr.to_le_bytes().to_vec(),
+                    address: Some(addr),
+                    optimized_out: false,
+                })
+            }
+            Scalar::Pointer(addr, target) if !matches!(target.strip().kind, TypeKind::Void) => {
+                self.read(addr, target)
+            }
+            _ => Err("Attempt to take contents of a non-pointer value.".to_string()),
+        }
+    }
+
+    fn index(&self, base: Value, index: Value) -> Result<Value, String> {
+        let index = match self.scalar(&index)? {
+            Scalar::Int(index, _) => index,
+            _ => return Err("Invalid array index.".to_string()),
+        };
+        // Arrays that are not in memory, such as members of a returned struct, are indexed in
+        // place
+        if let (TypeKind::Array(element, _), None) = (&base.entity_type.strip().kind, base.address)
+        {
+            let size = element.size as usize;
+            let start = usize::try_from(index)
+                .ok()
+                .and_then(|index| index.checked_mul(size));
+            let range = start.and_then(|start| Some(start..start.checked_add(size)?));
+            return match range.and_then(|range| base.bytes.get(range)) {
+                Some(bytes) => Ok(Value {
+                    entity_type: (**element).clone(),
+                    bytes: bytes.to_vec(),
+                    address: None,
+                    optimized_out: base.optimized_out,
+                }),
+                None => Err("no such vector element".to_string()),
+            };
+        }
+        match self.scalar(&base)? {
+            Scalar::Pointer(addr, target) if target.size > 0 => {
+                // Like pointer arithmetic in C, the address wraps around
+                let addr = addr.wrapping_add(index.wrapping_mul(target.size as i64) as u64);
+                self.read(addr, target)
+            }
+            _ => Err("cannot subscript something of a non-array, non-pointer type".to_string()),
+        }
+    }
+
+    fn unary(&self, op: UnaryOp, operand: Value) -> Result<Value, String> {
+        match op {
+            UnaryOp::Deref => return self.dereference(operand),
+            UnaryOp::AddressOf => {
+                let addr = operand.address.ok_or_else(|| {
+                    "Attempt to take address of value not located in memory.".to_string()
+                })?;
+                return Ok(Value::from_i64(
+                    addr as i64,
+                    operand.entity_type.pointer_to(),
+                ));
+            }
+            _ => {}
+        }
+        let scalar = self.scalar(&operand)?;
+        match (op, scalar) {
+            (UnaryOp::Not, scalar) => Ok(Value::from_i64(!is_true(&scalar)? as i64, int())),
+            (UnaryOp::Plus, Scalar::Int(value, ty)) => Ok(Value::from_i64(value, promote(ty))),
+            (UnaryOp::Neg, Scalar::Int(value, ty)) => {
+                Ok(Value::from_i64(value.wrapping_neg(), promote(ty)))
+            }
+            (UnaryOp::BitNot, Scalar::Int(value, ty)) => Ok(Value::from_i64(!value, promote(ty))),
+            (UnaryOp::Plus, Scalar::Float(value)) => Ok(Value::from_f64(value, double())),
+            (UnaryOp::Neg, Scalar::Float(value)) => Ok(Value::from_f64(-value, double())),
+            _ => Err("Argument to arithmetic operation not a number or boolean.".to_string()),
+        }
+    }
+
+    fn binary(&self, op: BinaryOp, lhs: Value, rhs: Value) -> Result<Value, String> {
+        let (lhs, rhs) = (self.scalar(&lhs)?, self.scalar(&rhs)?);
+        let compare = |ordering: std::cmp::Ordering| {
+            let result = match op {
+                BinaryOp::Lt => ordering.is_lt(),
+                BinaryOp::Gt => ordering.is_gt(),
+                BinaryOp::Le => ordering.is_le(),
+                BinaryOp::Ge => ordering.is_ge(),
+                BinaryOp::Eq => ordering.is_eq(),
+                _ => ordering.is_ne(),
+            };
+            Ok(Value::from_i64(result as i64, int()))
+        };
+        let is_comparison = matches!(
+            op,
+            BinaryOp::Lt | BinaryOp::Gt | BinaryOp::Le | BinaryOp::Ge | BinaryOp::Eq | BinaryOp::Ne
+        );
+        match (lhs, rhs) {
+            // Pointer arithmetic scales by the size of the type pointed to
+            (Scalar::Pointer(addr, target), Scalar::Int(offset, _))
+                if matches!(op, BinaryOp::Add | BinaryOp::Sub) =>
+            {
+                let offset = offset.wrapping_mul(target.size.max(1) as i64);
+                let offset = if op == BinaryOp::Sub {
+                    offset.wrapping_neg()
+                } else {
+                    offset
+                };
+                Ok(Value::from_i64(
+                    addr.wrapping_add(offset as u64) as i64,
+                    target.pointer_to(),
+                ))
+            }
+            (Scalar::Int(offset, _), Scalar::Pointer(addr, target)) if op == BinaryOp::Add => {
+                let offset = offset.wrapping_mul(target.size.max(1) as i64);
+                Ok(Value::from_i64(
+                    addr.wrapping_add(offset as u64) as i64,
+                    target.pointer_to(),
+                ))
+            }
+            (Scalar::Pointer(lhs, target), Scalar::Pointer(rhs, _)) if op == BinaryOp::Sub => {
+                let difference = lhs.wrapping_sub(rhs) as i64 / target.size.max(1) as i64;
+                Ok(Value::from_i64(difference, long()))
+            }
+            (Scalar::Pointer(lhs, _), Scalar::Pointer(rhs, _)) if is_comparison => {
+                compare(lhs.cmp(&rhs))
+            }
+            // Pointers may be compared with integers such as 0
+            (Scalar::Pointer(lhs, _), Scalar::Int(rhs, _)) if is_comparison => {
+                compare(lhs.cmp(&(rhs as u64)))
+            }
+            (Scalar::Int(lhs, _), Scalar::Pointer(rhs, _)) if is_comparison => {
+                compare((lhs as u64).cmp(&rhs))
+            }
+            (Scalar::Pointer(..), _) | (_, Scalar::Pointer(..)) => {
+                Err("Argument to arithmetic operation not a number or boolean.".to_string())
+            }
+            (Scalar::Int(lhs, lhs_type), Scalar::Int(rhs, rhs_type)) => {
+                let ty = arithmetic_type(lhs_type, rhs_type);
+                let unsigned = ty.encoding == Some(gimli::DW_ATE_unsigned);
+                // Operands are converted to the common type first
+                let (lhs, rhs) = (ty.to_i64(lhs as u64), ty.to_i64(rhs as u64));
+                if is_comparison {
+                    return compare(if unsigned {
+                        (lhs as u64).cmp(&(rhs as u64))
+                    } else {
+                        lhs.cmp(&rhs)
+                    });
+                }
+                if matches!(op, BinaryOp::Div | BinaryOp::Rem) && rhs == 0 {
+                    return Err("Division by zero".to_string());
+                }
+                let result = match op {
+                    BinaryOp::Add => lhs.wrapping_add(rhs),
+                    BinaryOp::Sub => lhs.wrapping_sub(rhs),
+                    BinaryOp::Mul => lhs.wrapping_mul(rhs),
+                    BinaryOp::Div if unsigned => ((lhs as u64) / (rhs as u64)) as i64,
+                    BinaryOp::Div => lhs.wrapping_div(rhs),
+                    BinaryOp::Rem if unsigned => ((lhs as u64) % (rhs as u64)) as i64,
+                    BinaryOp::Rem => lhs.wrapping_rem(rhs),
+                    BinaryOp::Shl => lhs.wrapping_shl(rhs as u32),
+                    BinaryOp::Shr if unsigned => ((lhs as u64).wrapping_shr(rhs as u32)) as i64,
+                    BinaryOp::Shr => lhs.wrapping_shr(rhs as u32),
+                    BinaryOp::BitAnd => lhs & rhs,
+                    BinaryOp::BitXor => lhs ^ rhs,
+                    _ => lhs | rhs,
+                };
+                Ok(Value::from_i64(result, ty))
+            }
+            (lhs, rhs) => {
+                let (lhs, rhs) = (to_f64(&lhs), to_f64(&rhs));
+                if is_comparison {
+                    return match lhs.partial_cmp(&rhs) {
+                        Some(ordering) => compare(ordering),
+                        // Comparisons with NaN are false, except `!=`
+                        None => Ok(Value::from_i64((op == BinaryOp::Ne) as i64, int())),
+                    };
+                }
+                let result = match op {
+                    BinaryOp::Add => lhs + rhs,
+                    BinaryOp::Sub => lhs - rhs,
+                    BinaryOp::Mul => lhs * rhs,
+                    BinaryOp::Div => lhs / rhs,
+                    _ => return Err("Integer only operation.".to_string()),
+                };
+                Ok(Value::from_f64(result, double()))
+            }
+        }
+    }
+
+    fn cast(&self, operand: Value, ty: &Type) -> Result<Value, String> {
+        let target = ty.strip();
+        match &target.kind {
+            TypeKind::Struct(_) | TypeKind::Union(_) | TypeKind::Array(..) => {
+                if operand.entity_type.name == ty.name {
+                    return Ok(operand);
+                }
+                return Err("Invalid cast.".to_string());
+            }
+            TypeKind::Void => {
+                return Ok(Value {
+                    entity_type: ty.clone(),
+                    bytes: Vec::new(),
+                    address: None,
+                    optimized_out: false,
+                })
+            }
+            _ => {}
+        }
+        let scalar = self.scalar(&operand)?;
+        if target.encoding == Some(gimli::DW_ATE_float) {
+            return Ok(Value::from_f64(to_f64(&scalar), ty.clone()));
+        }
+        let value = match scalar {
+            Scalar::Int(value, _) => value,
+            Scalar::Float(value) => value as i64,
+            Scalar::Pointer(addr, _) => addr as i64,
+        };
+        if target.encoding == Some(gimli::DW_ATE_boolean) {
+            return Ok(Value::from_i64((value != 0) as i64, ty.clone()));
+        }
+        Ok(Value::from_i64(value, ty.clone()))
+    }
+
+    /// Reads a value of scalar type for arithmetic. Arrays in memory decay to a pointer to their
+    /// first element, as in C.
+    fn scalar(&self, value: &Value) -> Result<Scalar, String> {
+        if value.optimized_out {
+            return Err("value has been optimized out".to_string());
+        }
+        let ty = value.entity_type.strip();
+        let raw = to_word(&value.bytes);
+        match &ty.kind {
+            TypeKind::Pointer(pointee) => {
+                let target = self
+                    .debug_data
+                    .get_pointee(pointee)
+                    .cloned()
+                    .unwrap_or_else(Type::void);
+                Ok(Scalar::Pointer(raw, target))
+            }
+            TypeKind::Array(element, _) if value.address.is_some() => {
+                Ok(Scalar::Pointer(value.address.unwrap(), (**element).clone()))
+            }
+            TypeKind::Base | TypeKind::Enum(_) if ty.encoding == Some(gimli::DW_ATE_float) => {
+                Ok(Scalar::Float(float_value(ty, &value.bytes)))
+            }
+            TypeKind::Base | TypeKind::Enum(_) => Ok(Scalar::Int(ty.to_i64(raw), ty.clone())),
+            _ => Err(format!(
+                "Cannot use a value of type {} in an expression.",
+                value.entity_type.name
+            )),
+        }
+    }
+}
+
+/// A function used in an expression, which like in C stands for its address.
+fn function_value(function: &Function) -> Value {
+    let return_type = match &function.return_type {
+        Some(return_type) => return_type.name.as_str(),
+        None => "void",
+    };
+    let params: Vec<&str> = function
+        .variables
+        .iter()
+        .filter(|var| var.parameter)
+        .map(|var| var.entity_type.name.as_str())
+        .collect();
+    let name = format!(
+        "{} ({})",
+        return_type,
+        if params.is_empty() {
+            "void".to_string()
+        } else {
+            params.join(", ")
+        }
+    );
+    Value {
+        entity_type: Type::new(name, 1, TypeKind::Function),
+        bytes: function.address.to_le_bytes().to_vec(),
+        address: Some(function.address),
+        optimized_out: false,
+    }
+}
+
+/// Returns the type of the member called name of a struct or union type.
+fn member_type(ty: &Type, name: &str) -> Result<Type, String> {
+    match &ty.strip().kind {
+        TypeKind::Struct(members) | TypeKind::Union(members) => members
+            .iter()
+            .find(|member| member.name == name)
+            .map(|member| member.entity_type.clone())
+            .ok_or_else(|| format!("There is no member named {}.", name)),
+        _ => Err("Attempt to extract a component of a value that is not a structure.".to_string()),
+    }
+}
+
+fn member(base: Value, name: &str) -> Result<Value, String> {
+    let members = match &base.entity_type.strip().kind {
+        TypeKind::Struct(members) | TypeKind::Union(members) => members,
+        _ => {
+            return Err(format!(
+                "Attempt to extract a component of a value that is not a structure{}.",
+                if base.address.is_some() {
+                    ""
+                } else {
+                    " pointer"
+                }
+            ))
+        }
+    };
+    let member = members
+        .iter()
+        .find(|member| member.name == name)
+        .ok_or_else(|| format!("There is no member named {}.", name))?;
+    let start = (member.offset as usize).min(base.bytes.len());
+    let end = (start + member.entity_type.size as usize).min(base.bytes.len());
+    let bit_size = match member.bit_size {
+        Some(bit_size) => bit_size,
+        None => {
+            return Ok(Value {
+                entity_type: member.entity_type.clone(),
+                bytes: base.bytes[start..end].to_vec(),
+                address: base.address.map(|addr| addr + member.offset),
+                optimized_out: base.optimized_out,
+            })
+        }
+    };
+    // Bit fields are not addressable, so they are extracted into a value of their own
+    let raw = to_word(&base.bytes[start..base.bytes.len().min(start + 8)]) >> member.bit_offset;
+    let raw = if bit_size >= 64 {
+        raw
+    } else {
+        raw & ((1u64 << bit_size) - 1)
+    };
+    let bits = bit_size.min(64) as u32;
+    let signed = matches!(
+        member.entity_type.strip().encoding,
+        Some(gimli::DW_ATE_signed) | Some(gimli::DW_ATE_signed_char)
+    );
+    let value = if signed && bits < 64 {
+        ((raw << (64 - bits)) as i64) >> (64 - bits)
+    } else {
+        raw as i64
+    };
+    Ok(Value::from_i64(value, member.entity_type.clone()))
+}
+
+fn is_true(scalar: &Scalar) -> Result<bool, String> {
+    Ok(match scalar {
+        Scalar::Int(value, _) => *value != 0,
+        Scalar::Float(value) => *value != 0.0,
+        Scalar::Pointer(addr, _) => *addr != 0,
+    })
+}
+
+fn to_f64(scalar: &Scalar) -> f64 {
+    match scalar {
+        Scalar::Int(value, ty) if ty.encoding == Some(gimli::DW_ATE_unsigned) => {
+            *value as u64 as f64
+        }
+        Scalar::Int(value, _) => *value as f64,
+        Scalar::Float(value) => *value,
+        Scalar::Pointer(addr, _) => *addr as f64,
+    }
+}
+
+// Integer promotion: types narrower than int are computed as int
+fn promote(ty: Type) -> Type {
+    if ty.size < 4 || matches!(ty.kind, TypeKind::Enum(_)) && ty.size == 4 {
+        int()
+    } else {
+        ty
+    }
+}
+
+// The usual arithmetic conversions of C for two integer operands
+fn arithmetic_type(lhs: Type, rhs: Type) -> Type {
+    let (lhs, rhs) = (promote(lhs), promote(rhs));
+    let unsigned = |ty: &Type| ty.encoding == Some(gimli::DW_ATE_unsigned);
+    match lhs.size.cmp(&rhs.size) {
+        std::cmp::Ordering::Greater => lhs,
+        std::cmp::Ordering::Less => rhs,
+        std::cmp::Ordering::Equal if unsigned(&rhs) => rhs,
+        std::cmp::Ordering::Equal => lhs,
+    }
+}
+
+fn is_float(ty: &Type) -> bool {
+    ty.strip().encoding == Some(gimli::DW_ATE_float)
+}
+
+fn float_value(ty: &Type, bytes: &[u8]) -> f64 {
+    match ty.size {
+        4 => f32::from_bits(to_word(bytes) as u32) as f64,
+        8 => f64::from_bits(to_word(bytes)),
+        _ => value::extended_to_f64(bytes),
+    }
+}
+
+impl Value {
+    fn from_i64(value: i64, entity_type: Type) -> Value {
+        let size = (entity_type.size as usize).min(8);
+        Value {
+            bytes: value.to_le_bytes()[..size].to_vec(),
+            entity_type,
+            address: None,
+            optimized_out: false,
+        }
+    }
+
+    fn from_f64(value: f64, entity_type: Type) -> Value {
+        let bytes = match entity_type.size {
+            4 => (value as f32).to_le_bytes().to_vec(),
+            8 => value.to_le_bytes().to_vec(),
+            // Wider floats are stored as x87 extended precision
+            _ => value::f64_to_extended(value).to_vec(),
+        };
+        Value {
+            entity_type,
+            bytes,
+            address: None,
+            optimized_out: false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{self, Debuggee};
+
+    const SOURCE: &str = r#"
+struct node { int value; struct node *next; char name[12]; };
+typedef unsigned short ushort;
+struct node head = {7, 0, "head"};
+struct node *bad = (struct node *)8;
+int arr[5] = {1, 2, 3, 4, 5};
+ushort small = 3;
+int main(void) { return head.value; }
+"#;
+
+    /// The fixture program, stopped before its first instruction.
+    struct Fixture(Debuggee);
+
+    impl Fixture {
+        fn new() -> Fixture {
+            Fixture(testing::start(SOURCE))
+        }
+
+        fn parse(&self, text: &str) -> Result<Expr, String> {
+            parse(text, &self.0.debug_data)
+        }
+
+        /// Evaluates text and formats the result like `print` does.
+        fn print(&self, text: &str) -> Result<String, String> {
+            let frame = Frame::innermost(&self.0.inferior, &self.0.debug_data).unwrap();
+            let value = evaluate(&self.parse(text)?, &frame)?;
+            let bytes = &value.bytes;
+            Ok(value::format_value(
+                &value.entity_type,
+                bytes,
+                &self.0.inferior,
+                &self.0.debug_data,
+            ))
+        }
+    }
+
+    // Writes an expression fully parenthesized, to check how it was grouped
+    fn group(expr: &Expr) -> String {
+        match expr {
+            Expr::Int(value, _) => value.to_string(),
+            Expr::Float(value) => value.to_string(),
+            Expr::Char(c) => format!("'{}'", *c as char),
+            Expr::Variable(name) => name.clone(),
+            Expr::Member(base, name) => format!("{}.{}", group(base), name),
+            Expr::PointerMember(base, name) => format!("{}->{}", group(base), name),
+            Expr::Index(base, index) => format!("{}[{}]", group(base), group(index)),
+            Expr::Unary(op, operand) => format!("({:?} {})", op, group(operand)),
+            Expr::Binary(op, lhs, rhs) => format!("({} {:?} {})", group(lhs), op, group(rhs)),
+            Expr::Conditional(condition, then, other) => {
+                format!(
+                    "({} ? {} : {})",
+                    group(condition),
+                    group(then),
+                    group(other)
+                )
+            }
+            Expr::Cast(ty, operand) => format!("(({}) {})", ty.name, group(operand)),
+            Expr::SizeOf(operand) => format!("(sizeof {})", group(operand)),
+        }
+    }
+
+    #[test]
+    fn tokenize_operators_and_literals() {
+        assert_eq!(
+            tokenize("p->x<<=2").unwrap(),
+            vec![
+                Token::Ident("p".to_string()),
+                Token::Punct("->"),
+                Token::Ident("x".to_string()),
+                Token::Punct("<<"),
+                Token::Punct("="),
+                Token::Int(2, false, false),
+            ]
+        );
+        assert_eq!(
+            tokenize("0x1fUL 017 1e-3 .5f '\\n' '\\101' '\\x41' '\\0' $rip").unwrap(),
+            vec![
+                Token::Int(0x1f, true, true),
+                Token::Int(0o17, false, false),
+                Token::Float(1e-3),
+                Token::Float(0.5),
+                Token::Char(b'\n'),
+                Token::Char(b'A'),
+                Token::Char(b'A'),
+                Token::Char(0),
+                Token::Ident("$rip".to_string()),
+            ]
+        );
+        // In hex, e is a digit, so the minus is an operator whatever the case of the x
+        for text in ["0x1e-1", "0X1e-1"] {
+            assert_eq!(
+                tokenize(text).unwrap(),
+                vec![
+                    Token::Int(0x1e, false, false),
+                    Token::Punct("-"),
+                    Token::Int(1, false, false),
+                ]
+            );
+        }
+    }
+
+    #[test]
+    fn tokenize_errors() {
+        assert_eq!(
+            tokenize("1 @ 2").unwrap_err(),
+            "Invalid character '@' in expression."
+        );
+        assert_eq!(tokenize("'a").unwrap_err(), "Unmatched single quote.");
+        assert_eq!(
+            tokenize("'\\xg'").unwrap_err(),
+            "\\x escape sequence without any hex digits."
+        );
+        assert_eq!(
+            tokenize("'\\x141'").unwrap_err(),
+            "Hex escape \\x141 is out of range."
+        );
+        assert_eq!(
+            tokenize("'\\777'").unwrap_err(),
+            "Octal escape \\777 is out of range."
+        );
+        assert_eq!(tokenize("09").unwrap_err(), "Invalid number \"09\".");
+        assert_eq!(tokenize("1.2.3").unwrap_err(), "Invalid number \"1.2.3\".");
+    }
+
+    #[test]
+    fn precedence() {
+        let fixture = Fixture::new();
+        let grouped = |text| group(&fixture.parse(text).unwrap());
+        assert_eq!(grouped("1 + 2 * 3"), "(1 Add (2 Mul 3))");
+        assert_eq!(grouped("1 - 2 - 3"), "((1 Sub 2) Sub 3)");
+        assert_eq!(grouped("a < b == c & d"), "(((a Lt b) Eq c) BitAnd d)");
+        assert_eq!(grouped("a || b && !c"), "(a Or (b And (Not c)))");
+        assert_eq!(grouped("a ? b : c ? d : e"), "(a ? b : (c ? d : e))");
+        assert_eq!(grouped("-*p->next[1].x"), "(Neg (Deref p->next[1].x))");
+        assert_eq!(
+            grouped("&arr[1] + 1 << 2"),
+            "(((AddressOf arr[1]) Add 1) Shl 2)"
+        );
+        assert_eq!(grouped("sizeof x + 1"), "((sizeof x) Add 1)");
+        assert_eq!(grouped("(a)"), "a");
+    }
+
+    #[test]
+    fn parse_casts() {
+        let fixture = Fixture::new();
+        let grouped = |text| group(&fixture.parse(text).unwrap());
+        assert_eq!(grouped("(unsigned char)x"), "((unsigned char) x)");
+        assert_eq!(
+            grouped("(long unsigned int)-1"),
+            "((long unsigned int) (Neg 1))"
+        );
+        assert_eq!(grouped("(struct node *)p"), "((struct node *) p)");
+        assert_eq!(grouped("(const ushort)1"), "((ushort) 1)");
+        assert_eq!(grouped("sizeof(int) * 2"), "(4 Mul 2)");
+    }
+
+    #[test]
+    fn parse_errors() {
+        let fixture = Fixture::new();
+        let error = |text| fixture.parse(text).unwrap_err();
+        assert_eq!(error("1 +"), "A syntax error in expression, near `'.");
+        assert_eq!(error("(1 + 2"), "A syntax error in expression, near `'.");
+        assert_eq!(error("1 2"), "A syntax error in expression, near `2'.");
+        assert_eq!(error("(const)1"), "A type name is required.");
+        assert_eq!(error("sizeof(volatile)"), "A type name is required.");
+        assert_eq!(error("(struct nope *)0"), "No struct type named nope.");
+    }
+
+    #[test]
+    fn evaluate_arithmetic_and_casts() {
+        let fixture = Fixture::new();
+        let print = |text| fixture.print(text).unwrap();
+        assert_eq!(print("1 + 2 * 3"), "7");
+        assert_eq!(print("7 / 2"), "3");
+        assert_eq!(print("-7 % 3"), "-1");
+        assert_eq!(print("7 / 2.0"), "3.5");
+        assert_eq!(print("(char)65"), "65 'A'");
+        assert_eq!(print("(unsigned char)300"), "44 ','");
+        assert_eq!(print("(int)2.9"), "2");
+        assert_eq!(print("-1U > 0"), "1");
+        assert_eq!(print("small << 2"), "12");
+        assert_eq!(print("head.value ? arr[4] : 0"), "5");
+        assert_eq!(print("*(arr + 2)"), "3");
+        assert_eq!(print("&arr[3] - &arr[1]"), "2");
+    }
+
+    #[test]
+    fn evaluate_sizeof() {
+        let fixture = Fixture::new();
+        let print = |text| fixture.print(text).unwrap();
+        assert_eq!(print("sizeof(struct node)"), "32");
+        assert_eq!(print("sizeof head.name"), "12");
+        assert_eq!(print("sizeof arr"), "20");
+        assert_eq!(print("sizeof(arr + 1)"), "8");
+        assert_eq!(print("sizeof(small + small)"), "4");
+        assert_eq!(print("sizeof(ushort)"), "2");
+        assert_eq!(print("sizeof 1.0"), "8");
+        // The operand is not evaluated, so bad pointers are fine
+        assert_eq!(print("sizeof(*bad)"), "32");
+        assert_eq!(print("sizeof bad->next->name[1]"), "1");
+    }
+
+    #[test]
+    fn evaluate_errors() {
+        let fixture = Fixture::new();
+        let error = |text| fixture.print(text).unwrap_err();
+        assert_eq!(error("nope"), "No symbol \"nope\" in current context.");
+        assert_eq!(error("1 / 0"), "Division by zero");
+        assert_eq!(error("*bad"), "Cannot access memory at address 0x8");
+        assert_eq!(
+            error("*head.value"),
+            "Attempt to take contents of a non-pointer value."
+        );
+        assert_eq!(error("head.nope"), "There is no member named nope.");
+        // Like in C, the address wraps around rather than overflowing
+        assert!(error("arr[2305843009213693952]").starts_with("Cannot access memory"));
+        assert_eq!(error("sizeof(*head.value)"), error("*head.value"));
+    }
+}
@@ -5,18 +5,22 @@
 //!
 //! This code is a huge mess. Please don't read it unless you're trying to do an extension :)
 
-use gimli;
 use gimli::{UnitOffset, UnitSectionOffset};
 use object::{Object, ObjectSection};
 use std::borrow;
 //use std::io::{BufWriter, Write};
-use crate::dwarf_data::{File, Function, Line, Location, Type, Variable};
+use crate::dwarf_data::{
+    pointer_name, Enumerator, Expression, File, Function, Line, Location, LocationListEntry,
+    Member, Pointee, Type, TypeId, TypeKind, Variable,
+};
 use std::collections::HashMap;
-use std::convert::TryInto;
 use std::fmt::Write;
 use std::{io, path};
 
-pub fn load_file(object: &object::File, endian: gimli::RunTimeEndian) -> Result<Vec<File>, Error> {
+pub fn load_file(
+    object: &object::File,
+    endian: gimli::RunTimeEndian,
+) -> Result<(Vec<File>, HashMap<TypeId, Type>), Error> {
     // Load a section and return as `Cow<[u8]>`.
     let load_section = |id: gimli::SectionId| -> Result<borrow::Cow<[u8]>, gimli::Error> {
         match object.section_by_name(id.name()) {
@@ -37,13 +41,27 @@ pub fn load_file(object: &object::File, endian: gimli::RunTimeEndian) -> Result<
     let borrow_section: &dyn for<'a> Fn(
         &'a borrow::Cow<[u8]>,
     ) -> gimli::EndianSlice<'a, gimli::RunTimeEndian> =
-        &|section| gimli::EndianSlice::new(&*section, endian);
+        &|section| gimli::EndianSlice::new(section, endian);
 
     // Create `EndianSlice`s for all of the sections.
     let dwarf = dwarf_cow.borrow(&borrow_section);
 
+    // Types may refer to types declared later (or in another unit), so all type DIEs are read
+    // before any of them is built
+    let mut type_entries: HashMap<TypeId, TypeEntry> = HashMap::new();
+    let mut iter = dwarf.units();
+    while let Some(header) = iter.next()? {
+        let unit = dwarf.unit(header)?;
+        collect_type_entries(&unit, &dwarf, &mut type_entries)?;
+    }
+    let builder = TypeBuilder {
+        entries: &type_entries,
+    };
     // Define a mapping from type offsets to type structs
-    let mut offset_to_type: HashMap<usize, Type> = HashMap::new();
+    let offset_to_type: HashMap<TypeId, Type> = type_entries
+        .keys()
+        .map(|offset| (*offset, builder.build(Some(*offset))))
+        .collect();
 
     let mut compilation_units: Vec<File> = Vec::new();
 
@@ -54,9 +72,17 @@ pub fn load_file(object: &object::File, endian: gimli::RunTimeEndian) -> Result<
 
         // Iterate over the Debugging Information Entries (DIEs) in the unit.
         let mut depth = 0;
+        // The lexical blocks enclosing the current entry, with their depths and address ranges
+        let mut blocks: Vec<(isize, Vec<(u64, u64)>)> = Vec::new();
         let mut entries = unit.entries();
         while let Some((delta_depth, entry)) = entries.next_dfs()? {
             depth += delta_depth;
+            while blocks
+                .last()
+                .is_some_and(|(block_depth, _)| *block_depth >= depth)
+            {
+                blocks.pop();
+            }
             // Update the offset_to_type mapping for types
             // Update the variable list for formal params/variables
             match entry.tag() {
@@ -77,33 +103,6 @@ pub fn load_file(object: &object::File, endian: gimli::RunTimeEndian) -> Result<
                         lines: Vec::new(),
                     });
                 }
-                gimli::DW_TAG_base_type => {
-                    let name = if let Ok(Some(attr)) = entry.attr(gimli::DW_AT_name) {
-                        if let Ok(DebugValue::Str(name)) = get_attr_value(&attr, &unit, &dwarf) {
-                            name
-                        } else {
-                            "<unknown>".to_string()
-                        }
-                    } else {
-                        "<unknown>".to_string()
-                    };
-                    let byte_size = if let Ok(Some(attr)) = entry.attr(gimli::DW_AT_byte_size) {
-                        if let Ok(DebugValue::Uint(byte_size)) =
-                            get_attr_value(&attr, &unit, &dwarf)
-                        {
-                            byte_size
-                        } else {
-                            // TODO: report error?
-                            0
-                        }
-                    } else {
-                        // TODO: report error?
-                        0
-                    };
-                    let type_offset = entry.offset().0;
-                    offset_to_type
-                        .insert(type_offset, Type::new(name, byte_size.try_into().unwrap()));
-                }
                 gimli::DW_TAG_subprogram => {
                     let mut func: Function = Default::default();
                     let mut attrs = entry.attrs();
@@ -118,30 +117,56 @@ pub fn load_file(object: &object::File, endian: gimli::RunTimeEndian) -> Result<
                             }
                             gimli::DW_AT_high_pc => {
                                 if let Ok(DebugValue::Uint(high_pc)) = val {
-                                    func.text_length = high_pc.try_into().unwrap();
+                                    func.text_length = high_pc;
                                 }
                             }
                             gimli::DW_AT_low_pc => {
                                 //println!("low pc {:?}", attr.value());
                                 if let Ok(DebugValue::Uint(low_pc)) = val {
-                                    func.address = low_pc.try_into().unwrap();
+                                    func.address = low_pc;
                                 }
                             }
                             gimli::DW_AT_decl_line => {
                                 if let Ok(DebugValue::Uint(line_number)) = val {
-                                    func.line_number = line_number.try_into().unwrap();
+                                    func.line_number = line_number;
+                                }
+                            }
+                            gimli::DW_AT_type => {
+                                if let Ok(DebugValue::Size(offset)) = val {
+                                    func.return_type = offset_to_type.get(&offset).cloned();
                                 }
                             }
+                            gimli::DW_AT_frame_base => {
+                                func.frame_base = get_location(&attr, &unit, &dwarf);
+                            }
                             _ => {}
                         }
                     }
                     compilation_units.last_mut().unwrap().functions.push(func);
                 }
+                gimli::DW_TAG_lexical_block => {
+                    let mut ranges = Vec::new();
+                    let mut iter = dwarf.die_ranges(&unit, entry)?;
+                    while let Some(range) = iter.next()? {
+                        ranges.push((range.begin, range.end));
+                    }
+                    // A block without addresses of its own is as visible as its parent
+                    if ranges.is_empty() {
+                        if let Some((_, parent)) = blocks.last() {
+                            ranges = parent.clone();
+                        }
+                    }
+                    if !ranges.is_empty() {
+                        blocks.push((depth, ranges));
+                    }
+                }
                 gimli::DW_TAG_formal_parameter | gimli::DW_TAG_variable => {
                     let mut name = String::new();
                     let mut entity_type: Option<Type> = None;
                     let mut location: Option<Location> = None;
                     let mut line_number = 0;
+                    // Set for declarations of variables defined elsewhere, e.g. `extern int g;`
+                    let mut declaration = false;
                     let mut attrs = entry.attrs();
                     while let Some(attr) = attrs.next()? {
                         let val = get_attr_value(&attr, &unit, &dwarf);
@@ -154,13 +179,13 @@ pub fn load_file(object: &object::File, endian: gimli::RunTimeEndian) -> Result<
                             }
                             gimli::DW_AT_type => {
                                 if let Ok(DebugValue::Size(offset)) = val {
-                                    if let Some(dtype) = offset_to_type.get(&offset).clone() {
+                                    if let Some(dtype) = offset_to_type.get(&offset) {
                                         entity_type = Some(dtype.clone());
                                     }
                                 }
                             }
                             gimli::DW_AT_location => {
-                                if let Some(loc) = get_location(&attr, &unit) {
+                                if let Some(loc) = get_location(&attr, &unit, &dwarf) {
                                     location = Some(loc);
                                 }
                             }
@@ -169,15 +194,26 @@ pub fn load_file(object: &object::File, endian: gimli::RunTimeEndian) -> Result<
                                     line_number = num;
                                 }
                             }
+                            gimli::DW_AT_declaration => {
+                                declaration = true;
+                            }
                             _ => {}
                         }
                     }
-                    if entity_type.is_some() && location.is_some() {
+                    // A local without a location was optimized out entirely. Unnamed parameters
+                    // belong to function types rather than to functions, and a declaration
+                    // leaves the location to the definition, which is found as a global.
+                    if depth > 1 && location.is_none() && !name.is_empty() && !declaration {
+                        location = Some(Location::List(Vec::new()));
+                    }
+                    if let (Some(entity_type), Some(location)) = (entity_type, location) {
                         let var = Variable {
                             name,
-                            entity_type: entity_type.unwrap(),
-                            location: location.unwrap(),
-                            line_number: line_number.try_into().unwrap(),
+                            entity_type,
+                            location,
+                            line_number,
+                            parameter: entry.tag() == gimli::DW_TAG_formal_parameter,
+                            scope: blocks.last().map(|(_, ranges)| ranges.clone()),
                         };
                         if depth == 1 {
                             compilation_units
@@ -247,22 +283,364 @@ pub fn load_file(object: &object::File, endian: gimli::RunTimeEndian) -> Result<
             }
         }
     }
-    Ok(compilation_units)
+    Ok((compilation_units, offset_to_type))
+}
+
+// The attributes of a type DIE needed to build its Type. Other types are referred to by offset,
+// since they may not have been read yet.
+struct TypeEntry {
+    tag: gimli::DwTag,
+    name: Option<String>,
+    byte_size: Option<u64>,
+    encoding: Option<gimli::DwAte>,
+    target: Option<TypeId>, // DW_AT_type
+    children: Vec<TypeEntryChild>,
+}
+
+// A member, subrange, enumerator or parameter DIE nested in a type DIE
+#[derive(Default)]
+struct TypeEntryChild {
+    name: Option<String>,
+    target: Option<TypeId>,
+    offset: u64,
+    bit_size: Option<u64>,
+    bit_offset: u64,
+    count: Option<u64>,
+    value: i64,
+}
+
+fn collect_type_entries<R: Reader>(
+    unit: &gimli::Unit<R>,
+    dwarf: &gimli::Dwarf<R>,
+    type_entries: &mut HashMap<TypeId, TypeEntry>,
+) -> Result<(), Error> {
+    // The type DIEs enclosing the current DIE, with their depth
+    let mut parents: Vec<(isize, TypeId)> = Vec::new();
+    let mut depth = 0;
+    let mut entries = unit.entries();
+    while let Some((delta_depth, entry)) = entries.next_dfs()? {
+        depth += delta_depth;
+        while parents
+            .last()
+            .is_some_and(|(parent_depth, _)| *parent_depth >= depth)
+        {
+            parents.pop();
+        }
+        let mut name = None;
+        let mut target = None;
+        let mut byte_size = None;
+        let mut child: TypeEntryChild = Default::default();
+        let mut attrs = entry.attrs();
+        while let Some(attr) = attrs.next()? {
+            match attr.name() {
+                gimli::DW_AT_name => {
+                    if let Ok(DebugValue::Str(attr_name)) = get_attr_value(&attr, unit, dwarf) {
+                        name = Some(attr_name);
+                    }
+                }
+                gimli::DW_AT_type => {
+                    if let Ok(DebugValue::Size(offset)) = get_attr_value(&attr, unit, dwarf) {
+                        target = Some(offset);
+                    }
+                }
+                gimli::DW_AT_byte_size => byte_size = attr.udata_value(),
+                gimli::DW_AT_data_member_location => {
+                    child.offset = get_member_offset(&attr, unit).unwrap_or(0);
+                }
+                gimli::DW_AT_bit_size => child.bit_size = attr.udata_value(),
+                gimli::DW_AT_data_bit_offset => {
+                    // Counted from the start of the struct rather than from the member's byte
+                    let bits = attr.udata_value().unwrap_or(0);
+                    child.offset = bits / 8;
+                    child.bit_offset = bits % 8;
+                }
+                gimli::DW_AT_count => child.count = attr.udata_value(),
+                gimli::DW_AT_upper_bound => {
+                    // Flexible array members have an upper bound of -1
+                    child.count = attr
+                        .sdata_value()
+                        .filter(|bound| *bound >= 0)
+                        .map(|bound| bound as u64 + 1);
+                }
+                gimli::DW_AT_const_value => {
+                    child.value = attr
+                        .sdata_value()
+                        .or_else(|| attr.udata_value().map(|value| value as i64))
+                        .unwrap_or(0);
+                }
+                _ => {}
+            }
+        }
+        match entry.tag() {
+            gimli::DW_TAG_base_type
+            | gimli::DW_TAG_pointer_type
+            | gimli::DW_TAG_structure_type
+            | gimli::DW_TAG_union_type
+            | gimli::DW_TAG_array_type
+            | gimli::DW_TAG_enumeration_type
+            | gimli::DW_TAG_typedef
+            | gimli::DW_TAG_const_type
+            | gimli::DW_TAG_volatile_type
+            | gimli::DW_TAG_restrict_type
+            | gimli::DW_TAG_subroutine_type => {
+                let offset = section_offset(entry.offset(), unit);
+                let encoding = match entry.attr_value(gimli::DW_AT_encoding)? {
+                    Some(gimli::AttributeValue::Encoding(encoding)) => Some(encoding),
+                    _ => None,
+                };
+                type_entries.insert(
+                    offset,
+                    TypeEntry {
+                        tag: entry.tag(),
+                        name,
+                        byte_size,
+                        encoding,
+                        target,
+                        children: Vec::new(),
+                    },
+                );
+                parents.push((depth, offset));
+            }
+            gimli::DW_TAG_member
+            | gimli::DW_TAG_subrange_type
+            | gimli::DW_TAG_enumerator
+            | gimli::DW_TAG_formal_parameter => {
+                if let Some((parent_depth, parent)) = parents.last() {
+                    if *parent_depth == depth - 1 {
+                        child.name = name;
+                        child.target = target;
+                        type_entries.get_mut(parent).unwrap().children.push(child);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+fn section_offset<R: Reader>(offset: UnitOffset, unit: &gimli::Unit<R>) -> TypeId {
+    match offset.to_unit_section_offset(unit) {
+        UnitSectionOffset::DebugInfoOffset(goff) => goff.0,
+        UnitSectionOffset::DebugTypesOffset(goff) => goff.0,
+    }
+}
+
+// DW_AT_data_member_location is either a constant or, in older DWARF, an expression adding the
+// offset to the struct's address
+fn get_member_offset<R: Reader>(attr: &gimli::Attribute<R>, unit: &gimli::Unit<R>) -> Option<u64> {
+    if let gimli::AttributeValue::Exprloc(ref data) = attr.value() {
+        let mut pc = data.0.clone();
+        return match gimli::Operation::parse(&mut pc, unit.encoding()) {
+            Ok(gimli::Operation::PlusConstant { value }) => Some(value),
+            _ => None,
+        };
+    }
+    attr.udata_value()
+}
+
+// Builds Types out of the collected type DIEs. A None offset stands for `void`.
+struct TypeBuilder<'a> {
+    entries: &'a HashMap<TypeId, TypeEntry>,
+}
+
+impl TypeBuilder<'_> {
+    fn build(&self, offset: Option<TypeId>) -> Type {
+        let entry = match offset.and_then(|offset| self.entries.get(&offset)) {
+            Some(entry) => entry,
+            None => return Type::void(),
+        };
+        let kind = match entry.tag {
+            gimli::DW_TAG_pointer_type => TypeKind::Pointer(match entry.target {
+                Some(target) if self.entries.contains_key(&target) => Pointee::Id(target),
+                _ => Pointee::Void,
+            }),
+            gimli::DW_TAG_structure_type => TypeKind::Struct(self.build_members(entry)),
+            gimli::DW_TAG_union_type => TypeKind::Union(self.build_members(entry)),
+            gimli::DW_TAG_array_type => {
+                let mut array = self.build_array(entry, &entry.children);
+                array.id = offset;
+                return array;
+            }
+            gimli::DW_TAG_enumeration_type => TypeKind::Enum(
+                entry
+                    .children
+                    .iter()
+                    .map(|child| Enumerator {
+                        name: child.name.clone().unwrap_or_default(),
+                        value: child.value,
+                    })
+                    .collect(),
+            ),
+            gimli::DW_TAG_typedef => TypeKind::Typedef(Box::new(self.build(entry.target))),
+            gimli::DW_TAG_const_type => TypeKind::Const(Box::new(self.build(entry.target))),
+            gimli::DW_TAG_volatile_type => TypeKind::Volatile(Box::new(self.build(entry.target))),
+            // Like GDB, `restrict` is only shown in the type's name
+            gimli::DW_TAG_restrict_type => {
+                let mut target = self.build(entry.target);
+                target.name = self.name(offset);
+                return target;
+            }
+            gimli::DW_TAG_subroutine_type => TypeKind::Function,
+            _ => TypeKind::Base,
+        };
+        Type {
+            id: offset,
+            name: self.name(offset),
+            size: self.size(offset),
+            encoding: self.encoding(offset),
+            kind,
+        }
+    }
+
+    fn build_members(&self, entry: &TypeEntry) -> Vec<Member> {
+        entry
+            .children
+            .iter()
+            .map(|child| Member {
+                name: child.name.clone().unwrap_or_default(),
+                entity_type: self.build(child.target),
+                offset: child.offset,
+                bit_size: child.bit_size,
+                bit_offset: child.bit_offset,
+            })
+            .collect()
+    }
+
+    // A multidimensional array has one subrange per dimension, and is built as an array of
+    // arrays
+    fn build_array(&self, entry: &TypeEntry, subranges: &[TypeEntryChild]) -> Type {
+        let (count, inner) = match subranges.split_first() {
+            Some((subrange, inner)) => (subrange.count, inner),
+            None => (None, subranges),
+        };
+        let element = if inner.is_empty() {
+            self.build(entry.target)
+        } else {
+            self.build_array(entry, inner)
+        };
+        Type {
+            id: None,
+            name: array_name(&element.name, count),
+            size: element.size * count.unwrap_or(0),
+            encoding: None,
+            kind: TypeKind::Array(Box::new(element), count),
+        }
+    }
+
+    fn name(&self, offset: Option<TypeId>) -> String {
+        let entry = match offset.and_then(|offset| self.entries.get(&offset)) {
+            Some(entry) => entry,
+            None => return "void".to_string(),
+        };
+        let tag_name = |keyword: &str| match &entry.name {
+            Some(name) => format!("{} {}", keyword, name),
+            None => format!("{} {{...}}", keyword),
+        };
+        match entry.tag {
+            gimli::DW_TAG_structure_type => tag_name("struct"),
+            gimli::DW_TAG_union_type => tag_name("union"),
+            gimli::DW_TAG_enumeration_type => tag_name("enum"),
+            gimli::DW_TAG_pointer_type => pointer_name(&self.name(entry.target)),
+            gimli::DW_TAG_const_type => self.qualified_name("const", entry.target),
+            gimli::DW_TAG_volatile_type => self.qualified_name("volatile", entry.target),
+            gimli::DW_TAG_restrict_type => self.qualified_name("restrict", entry.target),
+            gimli::DW_TAG_array_type => self.build_array(entry, &entry.children).name,
+            gimli::DW_TAG_subroutine_type => {
+                let params: Vec<String> = entry
+                    .children
+                    .iter()
+                    .map(|child| self.name(child.target))
+                    .collect();
+                let params = if params.is_empty() {
+                    "void".to_string()
+                } else {
+                    params.join(", ")
+                };
+                format!("{} ({})", self.name(entry.target), params)
+            }
+            _ => entry
+                .name
+                .clone()
+                .unwrap_or_else(|| "<unknown>".to_string()),
+        }
+    }
+
+    // Qualifiers of pointers follow the `*`, as in `char * const`
+    fn qualified_name(&self, qualifier: &str, target: Option<TypeId>) -> String {
+        let target_name = self.name(target);
+        if target_name.ends_with('*') {
+            format!("{} {}", target_name, qualifier)
+        } else {
+            format!("{} {}", qualifier, target_name)
+        }
+    }
+
+    fn size(&self, offset: Option<TypeId>) -> u64 {
+        let entry = match offset.and_then(|offset| self.entries.get(&offset)) {
+            Some(entry) => entry,
+            None => return 0,
+        };
+        if let Some(byte_size) = entry.byte_size {
+            return byte_size;
+        }
+        match entry.tag {
+            gimli::DW_TAG_pointer_type => 8,
+            gimli::DW_TAG_array_type => self.build_array(entry, &entry.children).size,
+            gimli::DW_TAG_typedef
+            | gimli::DW_TAG_const_type
+            | gimli::DW_TAG_volatile_type
+            | gimli::DW_TAG_restrict_type => self.size(entry.target),
+            _ => 0,
+        }
+    }
+
+    fn encoding(&self, offset: Option<TypeId>) -> Option<gimli::DwAte> {
+        let entry = self.entries.get(&offset?)?;
+        match entry.tag {
+            gimli::DW_TAG_base_type => entry.encoding,
+            gimli::DW_TAG_pointer_type => Some(gimli::DW_ATE_address),
+            gimli::DW_TAG_enumeration_type => {
+                Some(self.encoding(entry.target).unwrap_or(gimli::DW_ATE_signed))
+            }
+            gimli::DW_TAG_typedef
+            | gimli::DW_TAG_const_type
+            | gimli::DW_TAG_volatile_type
+            | gimli::DW_TAG_restrict_type => self.encoding(entry.target),
+            _ => None,
+        }
+    }
+}
+
+// `int [3]` with 2 more elements is `int [2][3]`
+fn array_name(element_name: &str, count: Option<u64>) -> String {
+    let dimension = match count {
+        Some(count) => format!("[{}]", count),
+        None => "[]".to_string(),
+    };
+    match element_name.find(" [") {
+        Some(index) => format!(
+            "{} {}{}",
+            &element_name[..index],
+            dimension,
+            &element_name[index + 1..]
+        ),
+        None => format!("{} {}", element_name, dimension),
+    }
 }
 
 #[derive(Debug, Clone)]
 pub enum DebugValue {
     Str(String),
     Uint(u64),
-    Int(i64),
     Size(usize),
     NoVal,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
+#[allow(clippy::enum_variant_names)]
 pub enum Error {
     GimliError(gimli::Error),
-    Addr2lineError(addr2line::gimli::Error),
     ObjectError(String),
     IoError,
 }
@@ -273,12 +651,6 @@ impl From<gimli::Error> for Error {
     }
 }
 
-// impl From<addr2line::gimli::Error> for Error {
-//     fn from(err: addr2line::gimli::Error) -> Self {
-//         Error::Addr2lineError(err)
-//     }
-// }
-
 impl From<io::Error> for Error {
     fn from(_: io::Error) -> Self {
         Error::IoError
@@ -298,25 +670,60 @@ impl<'input, Endian> Reader for gimli::EndianSlice<'input, Endian> where
 
 trait Reader: gimli::Reader<Offset = usize> + Send + Sync {}
 
-fn get_location<R: Reader>(attr: &gimli::Attribute<R>, unit: &gimli::Unit<R>) -> Option<Location> {
-    if let gimli::AttributeValue::Exprloc(ref data) = attr.value() {
-        let encoding = unit.encoding();
-        let mut pc = data.0.clone();
-        if pc.len() > 0 {
-            if let Ok(op) = gimli::Operation::parse(&mut pc, encoding) {
-                match op {
-                    gimli::Operation::FrameOffset { offset } => {
-                        return Some(Location::FramePointerOffset(offset.try_into().unwrap()));
-                    }
-                    gimli::Operation::Address { address } => {
-                        return Some(Location::Address(address.try_into().unwrap()));
-                    }
-                    _ => {}
-                }
-            }
+fn get_location<R: Reader>(
+    attr: &gimli::Attribute<R>,
+    unit: &gimli::Unit<R>,
+    dwarf: &gimli::Dwarf<R>,
+) -> Option<Location> {
+    let data = match attr.value() {
+        gimli::AttributeValue::Exprloc(data) => data,
+        value => return get_location_list(value, unit, dwarf).ok()?,
+    };
+    let encoding = unit.encoding();
+    // A lone DW_OP_addr is a fixed address, which can be used without a running process
+    let mut pc = data.0.clone();
+    if let Ok(gimli::Operation::Address { address }) = gimli::Operation::parse(&mut pc, encoding) {
+        if pc.is_empty() {
+            return Some(Location::Address(address));
         }
     }
-    None
+    Some(Location::Expression(get_expression(&data, encoding)?))
+}
+
+// Reads a location list from .debug_loc or .debug_loclists
+fn get_location_list<R: Reader>(
+    value: gimli::AttributeValue<R>,
+    unit: &gimli::Unit<R>,
+    dwarf: &gimli::Dwarf<R>,
+) -> Result<Option<Location>, gimli::Error> {
+    let mut locations = match dwarf.attr_locations(unit, value)? {
+        Some(locations) => locations,
+        None => return Ok(None),
+    };
+    let mut entries = Vec::new();
+    while let Some(entry) = locations.next()? {
+        if let Some(expression) = get_expression(&entry.data, unit.encoding()) {
+            entries.push(LocationListEntry {
+                begin: entry.range.begin,
+                end: entry.range.end,
+                expression,
+            });
+        }
+    }
+    Ok(Some(Location::List(entries)))
+}
+
+fn get_expression<R: Reader>(
+    data: &gimli::Expression<R>,
+    encoding: gimli::Encoding,
+) -> Option<Expression> {
+    let mut text = String::new();
+    dump_exprloc(&mut text, encoding, data).ok()?;
+    Some(Expression {
+        bytes: data.0.to_slice().ok()?.to_vec(),
+        encoding,
+        text,
+    })
 }
 
 // based on dwarf_dump.rs
@@ -345,7 +752,6 @@ fn get_attr_value<R: Reader>(
                 Ok(DebugValue::Str(format!("<.debug_str+0x{:08x}>", offset.0)))
             }
         }
-        gimli::AttributeValue::Sdata(data) => Ok(DebugValue::Int(data)),
         gimli::AttributeValue::Addr(data) => Ok(DebugValue::Uint(data)),
         gimli::AttributeValue::Udata(data) => Ok(DebugValue::Uint(data)),
 
@@ -1,11 +1,18 @@
-use crate::dwarf_data::DwarfData;
+use crate::breakpoint::Breakpoints;
+use crate::dwarf_data::{DwarfData, Function, Variable};
+use crate::location::Frame;
+use crate::memory::Memory;
+use crate::unwind::{self, StackFrame};
+use crate::value;
+use crate::watchpoint::{HwUnavailable, WatchKind};
 use nix::sys::ptrace;
 use nix::sys::signal;
 use nix::sys::signal::Signal;
 use nix::sys::wait::{waitpid, WaitPidFlag, WaitStatus};
 use nix::unistd::Pid;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::os::unix::process::CommandExt;
+use std::path::Path;
 use std::process::Child;
 use std::process::Command;
 
@@ -25,43 +32,60 @@ pub enum Status {
 /// This function calls ptrace with PTRACE_TRACEME to enable debugging on a process. You should use
 /// pre_exec with Command to call this in the child process.
 fn child_traceme() -> Result<(), std::io::Error> {
-    ptrace::traceme().or(Err(std::io::Error::new(
-        std::io::ErrorKind::Other,
-        "ptrace TRACEME failed",
-    )))
+    ptrace::traceme().or(Err(std::io::Error::other("ptrace TRACEME failed")))
 }
+
 #[derive(Clone)]
 struct Breakpoint {
-    addr: u64,
     orig_byte: u8,
 }
 
 pub struct Inferior {
     child: Child,
     addr_to_breakpoints: HashMap<u64, Breakpoint>,
+    /// The traps planted by the debugger itself at the return address of a call that `next` or
+    /// `finish` runs to the end. They stop the inferior whatever the user breakpoints there say.
+    internal_traps: HashSet<u64>,
     pending_signal: Option<signal::Signal>,
+    /// Set by wait() when the last stop was caused by executing one of our `0xcc` bytes
+    trapped_at_breakpoint: bool,
+    /// Which of the hardware debug registers DR0-DR3 hold a watchpoint
+    hw_slots: [bool; 4],
+    /// Set by wait() to the DR6 status bits of the debug registers that fired on the last stop
+    watch_triggers: u8,
+    memory: Memory,
 }
 
+/// Offset of the debug registers within `struct user`, as used by PTRACE_PEEKUSER/POKEUSER.
+const DEBUGREG_OFFSET: usize = std::mem::offset_of!(libc::user, u_debugreg);
+
+/// The PTRACE_GETREGSET type of the XSAVE area (from `<elf.h>`), and a size large enough for
+/// any XSAVE area
+const NT_X86_XSTATE: usize = 0x202;
+const XSTATE_MAX_SIZE: usize = 16384;
+
 impl Inferior {
     /// Attempts to start a new inferior process. Returns Some(Inferior) if successful, or None if
     /// an error is encountered.
-    pub fn new(target: &str, args: &Vec<String>, breakpoints: &Vec<u64>) -> Option<Inferior> {
-        // TODO: implement me!
+    pub fn new(target: &str, args: &[String], breakpoints: &[u64]) -> Option<Inferior> {
         let mut cmd = Command::new(target);
         cmd.args(args);
         unsafe {
             cmd.pre_exec(child_traceme);
         }
         let child = cmd.spawn().ok()?;
+        let pid = Pid::from_raw(child.id() as i32);
 
         let mut res = Inferior {
             child,
             addr_to_breakpoints: HashMap::new(),
+            internal_traps: HashSet::new(),
             pending_signal: None,
+            trapped_at_breakpoint: false,
+            hw_slots: [false; 4],
+            watch_triggers: 0,
+            memory: Memory::new(pid),
         };
-        for bp in breakpoints {
-            res.set_breakpoint(*bp).ok()?;
-        }
         match res.wait(Some(WaitPidFlag::WUNTRACED)).ok()? {
             Status::Stopped(signal, _rip) => {
                 if signal != Signal::SIGTRAP {
@@ -75,6 +99,9 @@ impl Inferior {
                 return None;
             }
         }
+        for bp in breakpoints {
+            res.set_breakpoint(*bp).ok()?;
+        }
 
         Some(res)
     }
@@ -86,12 +113,37 @@ impl Inferior {
 
     /// Calls waitpid on this inferior and returns a Status to indicate the state of the process
     /// after the waitpid call.
+    ///
+    /// If the inferior stopped because it executed one of our `0xcc` bytes, the instruction
+    /// pointer is rewound to the breakpoint address so that the stop is reported at the
+    /// breakpoint itself rather than one byte past it.
     pub fn wait(&mut self, options: Option<WaitPidFlag>) -> Result<Status, nix::Error> {
+        self.trapped_at_breakpoint = false;
+        self.watch_triggers = 0;
+        // The inferior has run since anything was cached
+        self.memory.invalidate();
         let status = match waitpid(self.pid(), options)? {
             WaitStatus::Exited(_pid, exit_code) => Status::Exited(exit_code),
             WaitStatus::Signaled(_pid, signal, _core_dumped) => Status::Signaled(signal),
             WaitStatus::Stopped(_pid, signal) => {
-                let regs = ptrace::getregs(self.pid())?;
+                let mut regs = ptrace::getregs(self.pid())?;
+                if signal == Signal::SIGTRAP
+                    && self
+                        .addr_to_breakpoints
+                        .contains_key(&regs.rip.wrapping_sub(1))
+                    && ptrace::getsiginfo(self.pid())?.si_code == libc::SI_KERNEL
+                {
+                    regs.rip -= 1;
+                    ptrace::setregs(self.pid(), regs)?;
+                    self.trapped_at_breakpoint = true;
+                } else if signal == Signal::SIGTRAP && self.hw_slots.contains(&true) {
+                    let dr6 = self.read_debug_register(6)?;
+                    self.watch_triggers = (dr6 & 0xf) as u8;
+                    if self.watch_triggers != 0 {
+                        // DR6 is sticky; clear it so the next stop is not mistaken for a hit
+                        self.write_debug_register(6, 0)?;
+                    }
+                }
                 Status::Stopped(signal, regs.rip)
             }
             other => panic!("waitpid returned unexpected status: {:?}", other),
@@ -104,60 +156,339 @@ impl Inferior {
         Ok(status)
     }
 
-    pub fn cont(&mut self) -> Result<Status, nix::Error> {
+    /// Returns the signal that should be delivered when the inferior is resumed. SIGTRAPs are
+    /// generated by the debugger itself and are never forwarded.
+    fn resume_signal(&self) -> Option<Signal> {
+        match self.pending_signal {
+            Some(Signal::SIGTRAP) => None,
+            x => x,
+        }
+    }
+
+    /// If the inferior is sitting on one of our breakpoints, temporarily restores the original
+    /// byte, executes that single instruction and re-inserts the `0xcc`. Returns the status after
+    /// the step, or None if there was no breakpoint to step over.
+    fn step_over_breakpoint(&mut self) -> Result<Option<Status>, nix::Error> {
         let regs = ptrace::getregs(self.pid())?;
-        let instruction_ptr = regs.rip - 1;
-        if let Some(breakpoint) = self.addr_to_breakpoints.get(&instruction_ptr) {
-            // Restore original byte at breakpoint
-            self.write_byte(instruction_ptr, breakpoint.orig_byte)?;
-            let mut new_regs = regs;
-            new_regs.rip = instruction_ptr;
-            ptrace::setregs(self.pid(), new_regs)?;
-            ptrace::step(self.pid(), None)?;
-            let status = self.wait(None)?;
-            if let Status::Stopped(signal, _) = status {
-                assert!(signal == Signal::SIGTRAP);
-            } else {
+        let instruction_ptr = regs.rip;
+        let orig_byte = match self.addr_to_breakpoints.get(&instruction_ptr) {
+            Some(breakpoint) => breakpoint.orig_byte,
+            None => return Ok(None),
+        };
+        self.write_byte(instruction_ptr, orig_byte)?;
+        ptrace::step(self.pid(), self.resume_signal())?;
+        let status = self.wait(None)?;
+        if let Status::Stopped(..) = status {
+            self.write_byte(instruction_ptr, 0xcc)?;
+        }
+        Ok(Some(status))
+    }
+
+    /// Resumes the inferior until it stops again. Traps at user breakpoints are checked against
+    /// `breakpoints`: if no breakpoint there wants to stop (e.g. its condition is false), the
+    /// inferior silently steps over the `0xcc` and keeps running.
+    /// Any other SIGTRAP is returned to the caller.
+    pub fn cont(
+        &mut self,
+        breakpoints: &mut Breakpoints,
+        debug_data: &DwarfData,
+    ) -> Result<Status, nix::Error> {
+        loop {
+            let pc = ptrace::getregs(self.pid())?.rip;
+            // Software watchpoints need the value checked after every instruction
+            let stepping = breakpoints.has_software_watchpoints();
+            // Whether the inferior only ran a single instruction
+            let (status, stepped) = match self.step_over_breakpoint()? {
+                Some(status) => (status, true),
+                None if stepping => (self.step()?, true),
+                None => {
+                    ptrace::cont(self.pid(), self.resume_signal())?;
+                    (self.wait(None)?, false)
+                }
+            };
+            let rip = match status {
+                Status::Stopped(Signal::SIGTRAP, rip) => rip,
+                other => return Ok(other),
+            };
+            let watch_stop = self.check_watchpoints(pc, breakpoints, debug_data)?;
+            // A single step can land on a breakpoint without executing its trap
+            if self.trapped_at_breakpoint || (stepped && self.has_breakpoint(rip)) {
+                // The user breakpoints are checked even at an internal trap or a watchpoint
+                // stop, to count their hits and report them along with the watchpoints
+                let user_stop = breakpoints.should_stop(rip, self, debug_data);
+                if user_stop || watch_stop || self.internal_traps.contains(&rip) {
+                    return Ok(status);
+                }
+                continue;
+            }
+            if watch_stop {
+                return Ok(status);
+            }
+            // A trap that is neither ours nor a watchpoint's, e.g. the program's own
+            // `raise(SIGTRAP)` or a hard-coded `int3`, is reported to the user
+            if !stepped && self.watch_triggers == 0 {
                 return Ok(status);
             }
-            self.set_breakpoint(instruction_ptr)?;
         }
-        let sig = match self.pending_signal {
-            Some(Signal::SIGTRAP) => None,
-            x => x,
-        };
-        ptrace::cont(self.pid(), sig)?;
-        self.wait(None)
     }
 
     // step forward by one instruction
     pub fn step(&mut self) -> Result<Status, nix::Error> {
-        let regs = ptrace::getregs(self.pid())?;
-        let instruction_ptr = regs.rip - 1;
-        if let Some(breakpoint) = self.addr_to_breakpoints.get(&instruction_ptr) {
-            // Restore original byte at breakpoint
-            self.write_byte(instruction_ptr, breakpoint.orig_byte)?;
-            let mut new_regs = regs;
-            new_regs.rip = instruction_ptr;
-            ptrace::setregs(self.pid(), new_regs)?;
-            ptrace::step(self.pid(), None)?;
-            let status = self.wait(None)?;
-            if let Status::Stopped(signal, _) = status {
-                assert!(signal == Signal::SIGTRAP);
-            } else {
-                return Ok(status);
+        if let Some(status) = self.step_over_breakpoint()? {
+            return Ok(status);
+        }
+        ptrace::step(self.pid(), self.resume_signal())?;
+        self.wait(None)
+    }
+
+    /// Executes a single instruction like step(), then checks the watchpoints, which can fire on
+    /// a single step just as when continuing. A watchpoint that fires is reported and recorded in
+    /// `breakpoints`, so that the stepping command stops there.
+    pub fn step_checked(
+        &mut self,
+        breakpoints: &mut Breakpoints,
+        debug_data: &DwarfData,
+    ) -> Result<Status, nix::Error> {
+        let pc = ptrace::getregs(self.pid())?.rip;
+        let status = self.step()?;
+        if let Status::Stopped(Signal::SIGTRAP, _) = status {
+            self.check_watchpoints(pc, breakpoints, debug_data)?;
+        }
+        Ok(status)
+    }
+
+    /// Checks the watchpoints after the inferior stopped, having run from pc. Returns true if a
+    /// watchpoint fired or went out of scope, which stops the inferior. Both are reported when
+    /// they happen at the same stop.
+    fn check_watchpoints(
+        &mut self,
+        pc: u64,
+        breakpoints: &mut Breakpoints,
+        debug_data: &DwarfData,
+    ) -> Result<bool, nix::Error> {
+        let left = breakpoints.leave_scopes(self)?;
+        // With software watchpoints the inferior only ever runs one instruction at a time
+        let stepping = breakpoints.has_software_watchpoints();
+        let fired = (self.watch_triggers != 0 || stepping)
+            && breakpoints.check_watchpoints(self.watch_triggers, self, debug_data);
+        if fired && stepping {
+            print_changing_instruction(pc, debug_data);
+        }
+        Ok(left || fired)
+    }
+
+    /// Executes the call instruction at the current instruction pointer and lets the callee run
+    /// until it returns, by planting a temporary breakpoint at the return address. If the
+    /// inferior stops anywhere else first (a user breakpoint, a signal, or exiting), that status
+    /// is returned instead.
+    pub fn step_over_call(
+        &mut self,
+        breakpoints: &mut Breakpoints,
+        debug_data: &DwarfData,
+    ) -> Result<Status, nix::Error> {
+        let caller_sp = ptrace::getregs(self.pid())?.rsp;
+        let status = self.step_checked(breakpoints, debug_data)?;
+        match status {
+            // Landing directly on a breakpoint at the callee's entry counts as hitting it, even
+            // when a watchpoint fired as well
+            Status::Stopped(Signal::SIGTRAP, rip) => {
+                let user_stop =
+                    self.has_breakpoint(rip) && breakpoints.should_stop(rip, self, debug_data);
+                if user_stop || breakpoints.has_hits() {
+                    return Ok(status);
+                }
             }
-            self.set_breakpoint(instruction_ptr)?;
+            other => return Ok(other),
         }
-        let sig = match self.pending_signal {
-            Some(Signal::SIGTRAP) => None,
-            x => x,
+        let return_addr = self.read_word(caller_sp - 8)?;
+        self.run_until_return(return_addr, caller_sp, breakpoints, debug_data)
+    }
+
+    /// Continues the inferior until the current frame returns to `return_addr`, after which the
+    /// stack pointer will be back at `caller_sp`. An internal trap is planted at the return
+    /// address for the duration of the call.
+    pub fn run_until_return(
+        &mut self,
+        return_addr: u64,
+        caller_sp: u64,
+        breakpoints: &mut Breakpoints,
+        debug_data: &DwarfData,
+    ) -> Result<Status, nix::Error> {
+        self.set_breakpoint(return_addr)?;
+        self.internal_traps.insert(return_addr);
+        let status = loop {
+            let status = self.cont(breakpoints, debug_data)?;
+            match status {
+                // A recursive call may hit the return address at a deeper frame; keep going
+                // until the stack pointer shows that our own call has returned.
+                Status::Stopped(Signal::SIGTRAP, rip)
+                    if rip == return_addr && ptrace::getregs(self.pid())?.rsp < caller_sp => {}
+                other => break other,
+            }
         };
-        ptrace::step(self.pid(), sig)?;
-        let status = self.wait(None)?;
+        self.internal_traps.remove(&return_addr);
+        if let Status::Stopped(..) = status {
+            if !breakpoints.is_enabled_at(return_addr) {
+                self.remove_breakpoint(return_addr)?;
+            }
+        }
         Ok(status)
     }
 
+    /// Programs a free hardware debug register to trap when the `len` bytes at addr are accessed
+    /// as described by `kind`. Returns the debug register used (0-3), or why none could be used:
+    /// all four are taken, or the range cannot be watched by a single register (it must be 1, 2,
+    /// 4 or 8 bytes long and aligned to its length).
+    pub fn insert_hw_watchpoint(
+        &mut self,
+        addr: u64,
+        len: u64,
+        kind: WatchKind,
+    ) -> Result<Result<usize, HwUnavailable>, nix::Error> {
+        let len_bits = match len {
+            1 => 0b00,
+            2 => 0b01,
+            4 => 0b11,
+            8 => 0b10,
+            _ => return Ok(Err(HwUnavailable::Uncovered)),
+        };
+        if !addr.is_multiple_of(len) {
+            return Ok(Err(HwUnavailable::Uncovered));
+        }
+        let slot = match self.hw_slots.iter().position(|used| !used) {
+            Some(slot) => slot,
+            None => return Ok(Err(HwUnavailable::NoFreeRegister)),
+        };
+        // x86 cannot trap on reads alone, so read watchpoints trap on any access and
+        // Watchpoint::triggered skips the writes
+        let rw_bits = match kind {
+            WatchKind::Write => 0b01,
+            WatchKind::Read | WatchKind::Access => 0b11,
+        };
+        self.write_debug_register(slot, addr)?;
+        let mut dr7 = self.read_debug_register(7)?;
+        dr7 &= !(0b1111 << (16 + 4 * slot));
+        dr7 |= (rw_bits | (len_bits << 2)) << (16 + 4 * slot);
+        dr7 |= 1 << (2 * slot);
+        self.write_debug_register(7, dr7)?;
+        self.hw_slots[slot] = true;
+        Ok(Ok(slot))
+    }
+
+    /// Disables the hardware watchpoint in debug register `slot`.
+    pub fn remove_hw_watchpoint(&mut self, slot: usize) -> Result<(), nix::Error> {
+        let dr7 = self.read_debug_register(7)?;
+        self.write_debug_register(7, dr7 & !(1 << (2 * slot)))?;
+        self.write_debug_register(slot, 0)?;
+        self.hw_slots[slot] = false;
+        Ok(())
+    }
+
+    fn read_debug_register(&self, index: usize) -> Result<u64, nix::Error> {
+        let offset = DEBUGREG_OFFSET + index * size_of::<u64>();
+        Ok(ptrace::read_user(self.pid(), offset as ptrace::AddressType)? as u64)
+    }
+
+    fn write_debug_register(&self, index: usize, value: u64) -> Result<(), nix::Error> {
+        let offset = DEBUGREG_OFFSET + index * size_of::<u64>();
+        unsafe {
+            ptrace::write_user(
+                self.pid(),
+                offset as ptrace::AddressType,
+                value as *mut std::ffi::c_void,
+            )
+        }
+    }
+
+    /// Reads the floating point/SSE registers of the inferior.
+    pub fn get_fpregs(&self) -> Result<libc::user_fpregs_struct, nix::Error> {
+        let mut fpregs = std::mem::MaybeUninit::<libc::user_fpregs_struct>::uninit();
+        let res = unsafe {
+            libc::ptrace(
+                libc::PTRACE_GETFPREGS,
+                self.pid().as_raw(),
+                std::ptr::null_mut::<libc::c_void>(),
+                fpregs.as_mut_ptr(),
+            )
+        };
+        nix::errno::Errno::result(res)?;
+        Ok(unsafe { fpregs.assume_init() })
+    }
+
+    /// Reads the XSAVE area of the inferior, which holds the AVX registers along with the ones
+    /// get_fpregs returns.
+    pub fn get_xstate(&self) -> Result<Vec<u8>, nix::Error> {
+        let mut xstate = vec![0u8; XSTATE_MAX_SIZE];
+        let mut iov = libc::iovec {
+            iov_base: xstate.as_mut_ptr() as *mut libc::c_void,
+            iov_len: xstate.len(),
+        };
+        let res = unsafe {
+            libc::ptrace(
+                libc::PTRACE_GETREGSET,
+                self.pid().as_raw(),
+                NT_X86_XSTATE as *mut libc::c_void,
+                &mut iov as *mut libc::iovec,
+            )
+        };
+        nix::errno::Errno::result(res)?;
+        // The kernel shrinks the length to the size of the area it filled in
+        xstate.truncate(iov.iov_len);
+        Ok(xstate)
+    }
+
+    /// Returns true if the instruction at the current instruction pointer is a `call`.
+    pub fn at_call_instruction(&self) -> Result<bool, nix::Error> {
+        let rip = ptrace::getregs(self.pid())?.rip;
+        Ok(is_call_instruction(&self.read_code(rip)?))
+    }
+
+    /// Reads the 16 bytes of machine code starting at addr.
+    fn read_code(&self, addr: u64) -> Result<Vec<u8>, nix::Error> {
+        self.read_memory(addr, 16)
+    }
+
+    /// Reads len bytes of memory as the program itself sees them, i.e. with any of our `0xcc`
+    /// bytes replaced by the original instruction bytes.
+    pub fn read_memory(&self, addr: u64, len: usize) -> Result<Vec<u8>, nix::Error> {
+        let mut bytes = self.read_bytes(addr, len)?;
+        self.hide_breakpoints(addr, &mut bytes);
+        Ok(bytes)
+    }
+
+    /// Like read_memory, but reads as many of the len bytes as possible: up to the first page
+    /// that cannot be read.
+    pub fn read_memory_prefix(&self, addr: u64, len: usize) -> Vec<u8> {
+        let (mut bytes, _) = self.memory.read_prefix(addr, len);
+        self.hide_breakpoints(addr, &mut bytes);
+        bytes
+    }
+
+    /// Replaces our `0xcc` bytes in the memory read from addr with the original bytes.
+    fn hide_breakpoints(&self, addr: u64, bytes: &mut [u8]) {
+        for (bp_addr, breakpoint) in &self.addr_to_breakpoints {
+            if *bp_addr >= addr && *bp_addr < addr + bytes.len() as u64 {
+                bytes[(*bp_addr - addr) as usize] = breakpoint.orig_byte;
+            }
+        }
+    }
+
+    /// Reads len bytes of the inferior's memory starting at addr.
+    pub fn read_bytes(&self, addr: u64, len: usize) -> Result<Vec<u8>, nix::Error> {
+        self.memory.read(addr, len)
+    }
+
+    /// Reads the 8-byte word at addr.
+    pub fn read_word(&self, addr: u64) -> Result<u64, nix::Error> {
+        let bytes = self.read_bytes(addr, 8)?;
+        Ok(u64::from_le_bytes(bytes.try_into().unwrap()))
+    }
+
+    /// Writes bytes to the inferior's memory starting at addr.
+    pub fn write_bytes(&mut self, addr: u64, bytes: &[u8]) -> Result<(), nix::Error> {
+        self.memory.write(addr, bytes)
+    }
+
     pub fn kill(&mut self) {
         match self.child.kill() {
             Ok(_) => {
@@ -168,61 +499,268 @@ impl Inferior {
         }
     }
 
-    pub fn print_backtrace(&self, debug_data: &DwarfData) -> Result<(), nix::Error> {
-        let regs = ptrace::getregs(self.pid())?;
-        let mut instruction_ptr = regs.rip;
-        let mut base_ptr = regs.rbp;
-        loop {
-            let debug_current_line = debug_data.get_line_from_addr(instruction_ptr);
-            let debug_current_func = debug_data.get_function_from_addr(instruction_ptr);
-            if debug_current_line.is_none() || debug_current_func.is_none() {
-                return Err(nix::Error::from(nix::errno::Errno::EINVAL));
+    /// Prints the call stack innermost frame first, stopping at main unless past_main is set.
+    /// With full, each frame's local variables are printed too.
+    pub fn print_backtrace(
+        &self,
+        debug_data: &DwarfData,
+        full: bool,
+        past_main: bool,
+    ) -> Result<(), nix::Error> {
+        for (index, frame) in unwind::unwind(self, debug_data)?.iter().enumerate() {
+            println!("{}", self.describe_frame(index, frame, debug_data));
+            let function = debug_data.get_function_containing(frame.pc);
+            if full {
+                self.print_locals(frame, function, debug_data);
             }
-            let current_line = debug_current_line.unwrap();
-            let current_func_name = debug_current_func.unwrap();
-            println!(
-                "{} ({}:{})",
-                current_func_name, current_line.file, current_line.number
-            );
-            if current_func_name == "main" {
+            if !past_main && function.is_some_and(|function| function.name == "main") {
                 break;
             }
-            let frame_top = base_ptr + 8;
-            // Read the return address and subtract 1 to point to the call instruction
-            // instead of the instruction after the call.
-            instruction_ptr = (ptrace::read(self.pid(), frame_top as ptrace::AddressType)? as u64).saturating_sub(1);
-            base_ptr = ptrace::read(self.pid(), base_ptr as ptrace::AddressType)? as u64;
         }
         Ok(())
     }
 
+    /// Describes a frame the way GDB does, e.g.
+    /// `#1  0x0000000000401196 in func2 (a=42, b=5) at function_calls.c:12`.
+    pub fn describe_frame(
+        &self,
+        index: usize,
+        frame: &StackFrame,
+        debug_data: &DwarfData,
+    ) -> String {
+        let mut text = format!("#{:<3}", index);
+        // The address is left out when a frame is stopped at the start of a line
+        if index > 0 || !debug_data.is_line_start(frame.pc) {
+            text.push_str(&format!("{:#018x} in ", frame.regs.rip));
+        }
+        text.push_str(&self.describe_call(frame, debug_data));
+        if let Some(line) = debug_data.get_line_from_addr(frame.pc) {
+            let file = Path::new(&line.file)
+                .file_name()
+                .map_or(line.file.clone(), |name| {
+                    name.to_string_lossy().into_owned()
+                });
+            text.push_str(&format!(" at {}:{}", file, line.number));
+        } else if let Some(library) = &frame.library {
+            text.push_str(&format!(" from {}", library));
+        }
+        text
+    }
+
+    /// Describes the call a frame is executing as its function and arguments, e.g.
+    /// `func2 (a=42, b=5)`.
+    pub fn describe_call(&self, frame: &StackFrame, debug_data: &DwarfData) -> String {
+        let function = debug_data.get_function_containing(frame.pc);
+        let name = match function {
+            Some(function) => Some(function.name.as_str()),
+            None => debug_data
+                .get_symbol_from_addr(frame.pc)
+                .map(|(name, _)| name)
+                .or(frame.symbol.as_deref()),
+        };
+        let args: Vec<String> = function
+            .iter()
+            .flat_map(|function| function.variables.iter())
+            .filter(|var| var.parameter)
+            .map(|var| {
+                let frame = self.frame(frame, debug_data);
+                let value = value::format_variable(var, &frame, value::format_argument);
+                format!("{}={}", var.name, value)
+            })
+            .collect();
+        format!("{} ({})", name.unwrap_or("??"), args.join(", "))
+    }
+
+    /// The registers and memory of a frame of the stack, for evaluating its variables.
+    pub fn frame<'a>(&'a self, frame: &StackFrame, debug_data: &'a DwarfData) -> Frame<'a> {
+        Frame {
+            regs: frame.regs,
+            pc: frame.pc,
+            inferior: self,
+            debug_data,
+        }
+    }
+
+    fn print_locals(
+        &self,
+        frame: &StackFrame,
+        function: Option<&Function>,
+        debug_data: &DwarfData,
+    ) {
+        let locals: Vec<&Variable> = function
+            .iter()
+            .flat_map(|function| function.variables_at(frame.pc))
+            .filter(|var| !var.parameter)
+            .collect();
+        if locals.is_empty() {
+            println!("No locals.");
+        }
+        let frame = self.frame(frame, debug_data);
+        for var in locals {
+            let value = value::format_variable(var, &frame, value::format_value);
+            println!("        {} = {}", var.name, value);
+        }
+    }
+
     pub fn set_breakpoint(&mut self, addr: u64) -> Result<u8, nix::Error> {
+        if let Some(breakpoint) = self.addr_to_breakpoints.get(&addr) {
+            return Ok(breakpoint.orig_byte);
+        }
         let orig_byte = self.write_byte(addr, 0xcc)?;
         self.addr_to_breakpoints
-            .insert(addr, Breakpoint { addr, orig_byte });
+            .insert(addr, Breakpoint { orig_byte });
         Ok(orig_byte)
     }
 
+    /// Returns true if a breakpoint is currently inserted at addr.
+    pub fn has_breakpoint(&self, addr: u64) -> bool {
+        self.addr_to_breakpoints.contains_key(&addr)
+    }
+
+    /// Removes the breakpoint at addr (if any), restoring the original instruction byte. An
+    /// internal trap stays in place until the call it waits for has returned.
+    pub fn remove_breakpoint(&mut self, addr: u64) -> Result<(), nix::Error> {
+        if self.internal_traps.contains(&addr) {
+            return Ok(());
+        }
+        if let Some(breakpoint) = self.addr_to_breakpoints.remove(&addr) {
+            self.write_byte(addr, breakpoint.orig_byte)?;
+        }
+        Ok(())
+    }
+
     fn write_byte(&mut self, addr: u64, val: u8) -> Result<u8, nix::Error> {
-        let aligned_addr = align_addr_to_word(addr);
-        let byte_offset = addr - aligned_addr;
-        let word = ptrace::read(self.pid(), aligned_addr as ptrace::AddressType)? as u64;
-        let orig_byte = (word >> 8 * byte_offset) & 0xff;
-        let masked_word = word & !(0xff << 8 * byte_offset);
-        let updated_word = masked_word | ((val as u64) << 8 * byte_offset);
-        (unsafe {
-            ptrace::write(
-                self.pid(),
-                aligned_addr as ptrace::AddressType,
-                updated_word as *mut std::ffi::c_void,
-            )
-        })?;
-        Ok(orig_byte as u8)
+        let orig_byte = self.read_bytes(addr, 1)?[0];
+        self.write_bytes(addr, &[val])?;
+        Ok(orig_byte)
+    }
+}
+
+/// Reports the instruction that changed the value of a software watchpoint.
+fn print_changing_instruction(pc: u64, debug_data: &DwarfData) {
+    print!("Changed by the instruction at {:#x}", pc);
+    if let Some((name, offset)) = debug_data.get_symbol_from_addr(pc) {
+        print!(" <{}+{}>", name, offset);
+    }
+    match debug_data.get_line_from_addr(pc) {
+        Some(line) => println!(" ({}:{})", line.file, line.number),
+        None => println!(),
     }
 }
 
-use std::mem::size_of;
+/// Decodes just enough of an x86-64 instruction to tell whether it is a `call`: either a direct
+/// `call rel32` (0xe8) or an indirect `call r/m` (0xff /2 or 0xff /3), possibly preceded by
+/// legacy and REX prefixes.
+fn is_call_instruction(code: &[u8]) -> bool {
+    let mut i = 0;
+    while i < code.len()
+        && matches!(
+            code[i],
+            0x66 | 0x67 | 0xf2 | 0xf3 | 0x2e | 0x3e | 0x26 | 0x36 | 0x64 | 0x65
+        )
+    {
+        i += 1;
+    }
+    if i < code.len() && (0x40..=0x4f).contains(&code[i]) {
+        i += 1;
+    }
+    match code.get(i) {
+        Some(0xe8) => true,
+        Some(0xff) => matches!(
+            code.get(i + 1).map(|modrm| (modrm >> 3) & 7),
+            Some(2) | Some(3)
+        ),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dwarf_data::Location;
+    use crate::testing;
+
+    #[test]
+    fn cont_stops_at_adjacent_breakpoints() {
+        let mut debuggee = testing::start("int main(void) { return 0; }\n");
+        let (debug_data, inferior) = (&debuggee.debug_data, &mut debuggee.inferior);
+        // `push %rbp` is a single byte, so the next instruction starts at main+1
+        let main = debug_data.find_function("main").unwrap().address;
+        let mut breakpoints = Breakpoints::new();
+        breakpoints.add(main, &format!("*{:#x}", main), None, false);
+        breakpoints.add(main + 1, &format!("*{:#x}", main + 1), None, false);
+        for addr in breakpoints.enabled_addrs() {
+            inferior.set_breakpoint(addr).unwrap();
+        }
+        for (addr, num) in [(main, 0), (main + 1, 1)] {
+            let status = inferior.cont(&mut breakpoints, debug_data).unwrap();
+            assert!(matches!(status, Status::Stopped(Signal::SIGTRAP, rip) if rip == addr));
+            assert_eq!(breakpoints.take_hits(), vec![num]);
+        }
+    }
 
-fn align_addr_to_word(addr: u64) -> u64 {
-    addr & (-(size_of::<u64>() as i64) as u64)
+    #[test]
+    fn cont_stops_at_traps_raised_by_the_program() {
+        let mut debuggee =
+            testing::start("#include <signal.h>\nint main(void) { raise(SIGTRAP); return 0; }\n");
+        let (debug_data, inferior) = (&debuggee.debug_data, &mut debuggee.inferior);
+        let mut breakpoints = Breakpoints::new();
+        let status = inferior.cont(&mut breakpoints, debug_data).unwrap();
+        assert!(matches!(status, Status::Stopped(Signal::SIGTRAP, _)));
+        assert!(!breakpoints.has_hits());
+    }
+
+    #[test]
+    fn cont_reports_a_breakpoint_where_a_watchpoint_fires() {
+        // A 3 byte array can only be watched in software, which single-steps onto line 4
+        let mut debuggee =
+            testing::start("char s[3];\nint main(void) {\n  s[0] = 1;\n  return 0;\n}\n");
+        let (debug_data, inferior) = (&debuggee.debug_data, &mut debuggee.inferior);
+        let line3 = debug_data.get_addr_for_line(None, 3).unwrap();
+        let line4 = debug_data.get_addr_for_line(None, 4).unwrap();
+        let mut breakpoints = Breakpoints::new();
+        breakpoints.add(line3, "3", None, false);
+        breakpoints.add(line4, "4", None, false);
+        for addr in breakpoints.enabled_addrs() {
+            inferior.set_breakpoint(addr).unwrap();
+        }
+        inferior.cont(&mut breakpoints, debug_data).unwrap();
+        assert_eq!(breakpoints.take_hits(), vec![0]);
+        let var = debug_data.get_variable_at_addr(line3, "s").unwrap().0;
+        let (addr, entity_type) = match var.location {
+            Location::Address(addr) => (addr, var.entity_type.clone()),
+            _ => panic!("s is not at a fixed address"),
+        };
+        let wp = breakpoints.add_watchpoint(WatchKind::Write, "s", addr, entity_type, None);
+        assert_eq!(wp.insert(inferior).unwrap(), Ok(()));
+        let status = inferior.cont(&mut breakpoints, debug_data).unwrap();
+        assert!(matches!(status, Status::Stopped(Signal::SIGTRAP, rip) if rip == line4));
+        assert!(breakpoints.has_hits());
+        assert_eq!(breakpoints.take_hits(), vec![1]);
+        assert_eq!(breakpoints.iter().nth(1).unwrap().hit_count, 1);
+    }
+
+    #[test]
+    fn rwatch_does_not_stop_at_stores() {
+        let mut debuggee = testing::start(
+            "int g;\nint h;\nint main(void) {\n  g = 1;\n  g = 2;\n  h = g;\n  return 0;\n}\n",
+        );
+        let (debug_data, inferior) = (&debuggee.debug_data, &mut debuggee.inferior);
+        let var = debug_data.get_variable_at_addr(0, "g").unwrap().0;
+        let (addr, entity_type) = match var.location {
+            Location::Address(addr) => (addr, var.entity_type.clone()),
+            _ => panic!("g is not at a fixed address"),
+        };
+        let mut breakpoints = Breakpoints::new();
+        let wp = breakpoints.add_watchpoint(WatchKind::Read, "g", addr, entity_type, None);
+        assert_eq!(wp.insert(inferior).unwrap(), Ok(()));
+        // The stores to g on lines 4 and 5 are skipped, and the load on line 6 stops
+        let status = inferior.cont(&mut breakpoints, debug_data).unwrap();
+        let rip = match status {
+            Status::Stopped(Signal::SIGTRAP, rip) => rip,
+            _ => panic!("the inferior did not stop at a trap"),
+        };
+        assert_eq!(debug_data.get_line_from_addr(rip).unwrap().number, 6);
+        assert_eq!(breakpoints.watchpoints().next().unwrap().hit_count, 1);
+    }
 }
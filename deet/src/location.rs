@@ -0,0 +1,256 @@
+//! Evaluates DWARF location expressions, which describe where a variable lives, against the
+//! registers and memory of the stopped inferior.
+
+use crate::dwarf_data::{DwarfData, Expression, Location};
+use crate::inferior::Inferior;
+use crate::unwind;
+use crate::value::to_word;
+use gimli::{EndianSlice, EvaluationResult, RunTimeEndian};
+use nix::sys::ptrace;
+
+/// Where one piece of a variable lives.
+pub enum Place {
+    Memory(u64),
+    /// A DWARF register number
+    Register(u16),
+    /// The value itself, computed by the expression (`DW_OP_stack_value`, `DW_OP_implicit_value`)
+    Value(Vec<u8>),
+    OptimizedOut,
+}
+
+/// A variable is made up of one or more pieces (`DW_OP_piece`), e.g. a struct whose fields were
+/// split across registers.
+pub struct Piece {
+    pub place: Place,
+    /// The size of the piece in bytes, or None if it is the whole variable
+    pub size: Option<u64>,
+}
+
+/// The registers and memory a location expression is evaluated against.
+pub struct Frame<'a> {
+    pub regs: libc::user_regs_struct,
+    /// The address to look up the executing function and location list entries by. This is
+    /// rip for the innermost frame, and rip - 1 for callers, whose rip is a return address.
+    pub pc: u64,
+    pub inferior: &'a Inferior,
+    pub debug_data: &'a DwarfData,
+}
+
+impl<'a> Frame<'a> {
+    /// The innermost frame, i.e. the one the inferior is stopped in.
+    pub fn innermost(
+        inferior: &'a Inferior,
+        debug_data: &'a DwarfData,
+    ) -> Result<Frame<'a>, nix::Error> {
+        let regs = ptrace::getregs(inferior.pid())?;
+        Ok(Frame {
+            regs,
+            pc: regs.rip,
+            inferior,
+            debug_data,
+        })
+    }
+}
+
+/// Works out where a variable stored at location currently lives.
+pub fn locate(location: &Location, frame: &Frame) -> Result<Vec<Piece>, String> {
+    match location {
+        Location::Address(addr) => Ok(vec![Piece {
+            place: Place::Memory(*addr),
+            size: None,
+        }]),
+        Location::Expression(expression) => evaluate(expression, frame),
+        Location::List(entries) => {
+            let pc = frame.pc;
+            match entries
+                .iter()
+                .find(|entry| entry.begin <= pc && pc < entry.end)
+            {
+                Some(entry) => evaluate(&entry.expression, frame),
+                None => Ok(vec![Piece {
+                    place: Place::OptimizedOut,
+                    size: None,
+                }]),
+            }
+        }
+    }
+}
+
+/// Returns true if any part of a variable is optimized out.
+pub fn is_optimized_out(pieces: &[Piece]) -> bool {
+    pieces
+        .iter()
+        .any(|piece| matches!(piece.place, Place::OptimizedOut))
+}
+
+/// Returns the address of a variable that lives in a single piece of memory, which is the case
+/// for everything built without optimization.
+pub fn address(pieces: &[Piece]) -> Option<u64> {
+    match pieces {
+        [Piece {
+            place: Place::Memory(addr),
+            size: None,
+        }] => Some(*addr),
+        _ => None,
+    }
+}
+
+/// Reads the size bytes of a variable from its pieces.
+pub fn read(pieces: &[Piece], size: usize, frame: &Frame) -> Result<Vec<u8>, String> {
+    let mut bytes = Vec::with_capacity(size);
+    for piece in pieces {
+        let len = piece.size.map_or(size, |size| size as usize);
+        let mut chunk = match &piece.place {
+            Place::Memory(addr) => frame
+                .inferior
+                .read_bytes(*addr, len)
+                .map_err(|_| format!("Cannot access memory at address {:#x}", addr))?,
+            Place::Register(register) => register_bytes(frame, *register)?,
+            Place::Value(value) => value.clone(),
+            Place::OptimizedOut => return Err("value has been optimized out".to_string()),
+        };
+        chunk.resize(len, 0);
+        bytes.extend_from_slice(&chunk);
+    }
+    bytes.resize(size, 0);
+    Ok(bytes)
+}
+
+/// Runs a DWARF expression. Register and memory operands are read from the inferior as the
+/// evaluation asks for them.
+fn evaluate(expression: &Expression, frame: &Frame) -> Result<Vec<Piece>, String> {
+    let bytecode = EndianSlice::new(&expression.bytes, RunTimeEndian::Little);
+    let mut evaluation = gimli::Evaluation::new(bytecode, expression.encoding);
+    let mut result = evaluation.evaluate();
+    loop {
+        result = match result.map_err(|e| format!("Bad DWARF expression: {}", e))? {
+            EvaluationResult::Complete => break,
+            EvaluationResult::RequiresRegister {
+                register,
+                base_type,
+            } if base_type.0 == 0 => {
+                let value = to_word(&register_bytes(frame, register.0)?);
+                evaluation.resume_with_register(gimli::Value::Generic(value))
+            }
+            EvaluationResult::RequiresMemory {
+                address,
+                size,
+                space: None,
+                base_type,
+            } if base_type.0 == 0 => {
+                let bytes = frame
+                    .inferior
+                    .read_bytes(address, size as usize)
+                    .map_err(|_| format!("Cannot access memory at address {:#x}", address))?;
+                evaluation.resume_with_memory(gimli::Value::Generic(to_word(&bytes)))
+            }
+            EvaluationResult::RequiresFrameBase => {
+                evaluation.resume_with_frame_base(frame_base(frame)?)
+            }
+            EvaluationResult::RequiresCallFrameCfa => {
+                let cfa = frame
+                    .debug_data
+                    .get_cfa(frame.pc, &frame.regs)
+                    .ok_or_else(|| "Could not compute the canonical frame address".to_string())?;
+                evaluation.resume_with_call_frame_cfa(cfa)
+            }
+            // Executables are not relocated
+            EvaluationResult::RequiresRelocatedAddress(address) => {
+                evaluation.resume_with_relocated_address(address)
+            }
+            // The value a parameter had on entry to the function cannot be recovered
+            EvaluationResult::RequiresEntryValue(_) => {
+                return Ok(vec![Piece {
+                    place: Place::OptimizedOut,
+                    size: None,
+                }])
+            }
+            other => {
+                return Err(format!(
+                    "Unhandled DWARF expression operation ({})",
+                    unhandled_name(&other)
+                ))
+            }
+        };
+    }
+    evaluation
+        .result()
+        .into_iter()
+        .map(|piece| {
+            let place = match piece.location {
+                gimli::Location::Empty => Place::OptimizedOut,
+                gimli::Location::Register { register } => Place::Register(register.0),
+                gimli::Location::Address { address } => Place::Memory(address),
+                gimli::Location::Value { value } => Place::Value(value_bytes(value)?),
+                gimli::Location::Bytes { value } => Place::Value(value.to_vec()),
+                gimli::Location::ImplicitPointer { .. } => Place::OptimizedOut,
+            };
+            Ok(Piece {
+                place,
+                size: piece.size_in_bits.map(|bits| bits.div_ceil(8)),
+            })
+        })
+        .collect()
+}
+
+fn unhandled_name(result: &EvaluationResult<EndianSlice<RunTimeEndian>>) -> &'static str {
+    match result {
+        EvaluationResult::RequiresTls(_) => "thread-local storage",
+        EvaluationResult::RequiresAtLocation(_) => "DW_OP_call",
+        EvaluationResult::RequiresParameterRef(_) => "DW_OP_GNU_parameter_ref",
+        EvaluationResult::RequiresIndexedAddress { .. } => "DW_OP_addrx",
+        _ => "typed value",
+    }
+}
+
+fn value_bytes(value: gimli::Value) -> Result<Vec<u8>, String> {
+    Ok(match value {
+        gimli::Value::F32(value) => value.to_le_bytes().to_vec(),
+        gimli::Value::F64(value) => value.to_le_bytes().to_vec(),
+        value => value
+            .to_u64(!0)
+            .map_err(|e| e.to_string())?
+            .to_le_bytes()
+            .to_vec(),
+    })
+}
+
+/// Computes the frame base that `DW_OP_fbreg` is relative to, from the `DW_AT_frame_base` of
+/// the function executing in frame.
+fn frame_base(frame: &Frame) -> Result<u64, String> {
+    let location = frame
+        .debug_data
+        .get_function_containing(frame.pc)
+        .and_then(|function| function.frame_base.as_ref())
+        .ok_or_else(|| "Could not find the frame base of the current function".to_string())?;
+    match locate(location, frame)?.as_slice() {
+        [Piece {
+            place: Place::Memory(addr),
+            ..
+        }] => Ok(*addr),
+        [Piece {
+            place: Place::Register(register),
+            ..
+        }] => Ok(to_word(&register_bytes(frame, *register)?)),
+        _ => Err("Unsupported frame base location".to_string()),
+    }
+}
+
+/// Reads a register by its DWARF number. General purpose registers are 8 bytes, SSE registers
+/// 16.
+fn register_bytes(frame: &Frame, register: u16) -> Result<Vec<u8>, String> {
+    if let Some(value) = unwind::register_value(&frame.regs, register) {
+        return Ok(value.to_le_bytes().to_vec());
+    }
+    match register {
+        17..=32 => {
+            let fpregs = frame.inferior.get_fpregs().map_err(|e| e.to_string())?;
+            let start = (register as usize - 17) * 4;
+            Ok(fpregs.xmm_space[start..start + 4]
+                .iter()
+                .flat_map(|word| word.to_le_bytes())
+                .collect())
+        }
+        _ => Err(format!("Unsupported DWARF register {}", register)),
+    }
+}
@@ -1,8 +1,19 @@
+mod breakpoint;
 mod debugger;
 mod debugger_command;
-mod inferior;
 mod dwarf_data;
+mod examine;
+mod expr;
 mod gimli_wrapper;
+mod inferior;
+mod location;
+mod memory;
+mod registers;
+#[cfg(test)]
+mod testing;
+mod unwind;
+mod value;
+mod watchpoint;
 
 use crate::debugger::Debugger;
 use nix::sys::signal::{signal, SigHandler, Signal};
@@ -0,0 +1,160 @@
+//! Reads and writes the memory of the inferior a page at a time, instead of a word at a time
+//! with `PTRACE_PEEKDATA`/`PTRACE_POKEDATA`.
+//!
+//! Pages are read with `process_vm_readv`, falling back to `/proc/<pid>/mem` for pages the
+//! inferior cannot read itself (it can also write read-only code, for breakpoints), and finally
+//! to ptrace. Pages are cached until the inferior runs again.
+
+use nix::errno::Errno;
+use nix::sys::ptrace;
+use nix::sys::uio::{process_vm_readv, RemoteIoVec};
+use nix::unistd::Pid;
+use std::cell::{OnceCell, RefCell};
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::IoSliceMut;
+use std::os::unix::fs::FileExt;
+
+pub const PAGE_SIZE: u64 = 4096;
+const WORD_SIZE: u64 = 8;
+
+pub struct Memory {
+    pid: Pid,
+    /// `/proc/<pid>/mem`, opened on first use: it refers to the address space the process had
+    /// when it was opened, which changes when the inferior calls exec
+    mem_file: OnceCell<Option<File>>,
+    /// The pages read since the inferior last stopped, by address
+    pages: RefCell<HashMap<u64, Vec<u8>>>,
+}
+
+impl Memory {
+    pub fn new(pid: Pid) -> Memory {
+        Memory {
+            pid,
+            mem_file: OnceCell::new(),
+            pages: RefCell::new(HashMap::new()),
+        }
+    }
+
+    /// Forgets the cached pages. Must be called whenever the inferior may have run.
+    pub fn invalidate(&self) {
+        self.pages.borrow_mut().clear();
+    }
+
+    /// Reads len bytes starting at addr. Fails if any of them is not mapped.
+    pub fn read(&self, addr: u64, len: usize) -> Result<Vec<u8>, nix::Error> {
+        match self.read_prefix(addr, len) {
+            (bytes, None) => Ok(bytes),
+            (_, Some(err)) => Err(err),
+        }
+    }
+
+    /// Reads up to len bytes starting at addr, stopping at the first page that cannot be read.
+    /// Returns the bytes before that page, and the error reading it if there is one.
+    pub fn read_prefix(&self, addr: u64, len: usize) -> (Vec<u8>, Option<nix::Error>) {
+        let end = match addr.checked_add(len as u64) {
+            Some(end) => end,
+            None => return (Vec::new(), Some(Errno::EFAULT)),
+        };
+        // len may be far more than is mapped, so the buffer grows as pages are read
+        let mut bytes = Vec::with_capacity(len.min(PAGE_SIZE as usize));
+        let mut pages = self.pages.borrow_mut();
+        let mut page = addr & !(PAGE_SIZE - 1);
+        while page < end {
+            let data = match pages.entry(page) {
+                Entry::Occupied(entry) => entry.into_mut(),
+                Entry::Vacant(entry) => match self.read_page(page) {
+                    Ok(data) => entry.insert(data),
+                    Err(err) => return (bytes, Some(err)),
+                },
+            };
+            let start = (addr.max(page) - page) as usize;
+            let stop = (end.min(page + PAGE_SIZE) - page) as usize;
+            bytes.extend_from_slice(&data[start..stop]);
+            page += PAGE_SIZE;
+        }
+        (bytes, None)
+    }
+
+    /// Writes bytes at addr, even if the memory is read-only to the inferior.
+    pub fn write(&self, addr: u64, bytes: &[u8]) -> Result<(), nix::Error> {
+        let written = self
+            .mem_file()
+            .is_some_and(|file| file.write_all_at(bytes, addr).is_ok());
+        if !written {
+            self.poke(addr, bytes)?;
+        }
+        // Keep the cached copies of the pages up to date
+        let end = addr + bytes.len() as u64;
+        for (page, data) in self.pages.borrow_mut().iter_mut() {
+            let start = addr.max(*page);
+            let stop = end.min(page + PAGE_SIZE);
+            if start < stop {
+                let source = &bytes[(start - addr) as usize..(stop - addr) as usize];
+                data[(start - page) as usize..(stop - page) as usize].copy_from_slice(source);
+            }
+        }
+        Ok(())
+    }
+
+    fn read_page(&self, page: u64) -> Result<Vec<u8>, nix::Error> {
+        let mut data = vec![0; PAGE_SIZE as usize];
+        let remote = [RemoteIoVec {
+            base: page as usize,
+            len: PAGE_SIZE as usize,
+        }];
+        let read = process_vm_readv(self.pid, &mut [IoSliceMut::new(&mut data)], &remote);
+        if read == Ok(PAGE_SIZE as usize) {
+            return Ok(data);
+        }
+        if let Some(file) = self.mem_file() {
+            if file.read_exact_at(&mut data, page).is_ok() {
+                return Ok(data);
+            }
+        }
+        for (index, chunk) in data.chunks_exact_mut(WORD_SIZE as usize).enumerate() {
+            let word_addr = page + index as u64 * WORD_SIZE;
+            let word = ptrace::read(self.pid, word_addr as ptrace::AddressType)? as u64;
+            chunk.copy_from_slice(&word.to_le_bytes());
+        }
+        Ok(data)
+    }
+
+    /// Writes bytes with ptrace, merging partial words with the bytes around them.
+    fn poke(&self, addr: u64, bytes: &[u8]) -> Result<(), nix::Error> {
+        let end = addr + bytes.len() as u64;
+        let mut word_addr = addr & !(WORD_SIZE - 1);
+        while word_addr < end {
+            let word = ptrace::read(self.pid, word_addr as ptrace::AddressType)? as u64;
+            let mut word_bytes = word.to_le_bytes();
+            for (offset, byte) in word_bytes.iter_mut().enumerate() {
+                let byte_addr = word_addr + offset as u64;
+                if byte_addr >= addr && byte_addr < end {
+                    *byte = bytes[(byte_addr - addr) as usize];
+                }
+            }
+            unsafe {
+                ptrace::write(
+                    self.pid,
+                    word_addr as ptrace::AddressType,
+                    u64::from_le_bytes(word_bytes) as *mut std::ffi::c_void,
+                )?;
+            }
+            word_addr += WORD_SIZE;
+        }
+        Ok(())
+    }
+
+    fn mem_file(&self) -> Option<&File> {
+        self.mem_file
+            .get_or_init(|| {
+                OpenOptions::new()
+                    .read(true)
+                    .write(true)
+                    .open(format!("/proc/{}/mem", self.pid))
+                    .ok()
+            })
+            .as_ref()
+    }
+}
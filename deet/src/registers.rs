@@ -0,0 +1,268 @@
+//! Names and formats the inferior's registers, for `info registers`, `$reg` in expressions and
+//! `set $reg = value`.
+
+use crate::dwarf_data::DwarfData;
+use crate::value::extended_to_f64;
+use std::fmt::{Display, LowerExp};
+
+/// The general purpose registers, in the order `info registers` lists them.
+pub const GENERAL_REGISTERS: [&str; 24] = [
+    "rax", "rbx", "rcx", "rdx", "rsi", "rdi", "rbp", "rsp", "r8", "r9", "r10", "r11", "r12", "r13",
+    "r14", "r15", "rip", "eflags", "cs", "ss", "ds", "es", "fs", "gs",
+];
+
+// The bits of eflags and mxcsr that GDB shows by name, lowest first
+const EFLAGS: [(u32, &str); 14] = [
+    (0, "CF"),
+    (2, "PF"),
+    (4, "AF"),
+    (6, "ZF"),
+    (7, "SF"),
+    (8, "TF"),
+    (9, "IF"),
+    (10, "DF"),
+    (11, "OF"),
+    (14, "NT"),
+    (16, "RF"),
+    (17, "VM"),
+    (18, "AC"),
+    (21, "ID"),
+];
+const MXCSR: [(u32, &str); 15] = [
+    (0, "IE"),
+    (1, "DE"),
+    (2, "ZE"),
+    (3, "OE"),
+    (4, "UE"),
+    (5, "PE"),
+    (6, "DAZ"),
+    (7, "IM"),
+    (8, "DM"),
+    (9, "ZM"),
+    (10, "OM"),
+    (11, "UM"),
+    (12, "PM"),
+    (13, "R0"),
+    (15, "FZ"),
+];
+
+// Where the upper halves of the ymm registers are in the XSAVE area, and the bit of its
+// XSTATE_BV header field saying whether they are in use
+const YMM_HI_OFFSET: usize = 576;
+const XSTATE_BV_OFFSET: usize = 512;
+const XSTATE_AVX: u64 = 1 << 2;
+
+/// Resolves the aliases GDB accepts for some registers.
+pub fn canonical_name(name: &str) -> &str {
+    match name {
+        "pc" => "rip",
+        "sp" => "rsp",
+        "fp" => "rbp",
+        name => name,
+    }
+}
+
+/// Reads a general purpose register by name.
+pub fn general_value(regs: &libc::user_regs_struct, name: &str) -> Option<u64> {
+    let mut regs = *regs;
+    general_slot(&mut regs, name).map(|slot| *slot)
+}
+
+/// Changes a general purpose register by name. Returns false if there is no such register.
+pub fn set_general_value(regs: &mut libc::user_regs_struct, name: &str, value: u64) -> bool {
+    match general_slot(regs, name) {
+        Some(slot) => {
+            *slot = value;
+            true
+        }
+        None => false,
+    }
+}
+
+fn general_slot<'a>(regs: &'a mut libc::user_regs_struct, name: &str) -> Option<&'a mut u64> {
+    Some(match canonical_name(name) {
+        "rax" => &mut regs.rax,
+        "rbx" => &mut regs.rbx,
+        "rcx" => &mut regs.rcx,
+        "rdx" => &mut regs.rdx,
+        "rsi" => &mut regs.rsi,
+        "rdi" => &mut regs.rdi,
+        "rbp" => &mut regs.rbp,
+        "rsp" => &mut regs.rsp,
+        "r8" => &mut regs.r8,
+        "r9" => &mut regs.r9,
+        "r10" => &mut regs.r10,
+        "r11" => &mut regs.r11,
+        "r12" => &mut regs.r12,
+        "r13" => &mut regs.r13,
+        "r14" => &mut regs.r14,
+        "r15" => &mut regs.r15,
+        "rip" => &mut regs.rip,
+        "eflags" => &mut regs.eflags,
+        "cs" => &mut regs.cs,
+        "ss" => &mut regs.ss,
+        "ds" => &mut regs.ds,
+        "es" => &mut regs.es,
+        "fs" => &mut regs.fs,
+        "gs" => &mut regs.gs,
+        "fs_base" => &mut regs.fs_base,
+        "gs_base" => &mut regs.gs_base,
+        _ => return None,
+    })
+}
+
+/// Formats the general purpose registers, plus fs_base and gs_base, as `info registers` rows:
+/// the name, the value in hex, and the value in its natural format.
+pub fn general_rows(
+    regs: &libc::user_regs_struct,
+    debug_data: &DwarfData,
+) -> Vec<(String, String)> {
+    GENERAL_REGISTERS
+        .iter()
+        .chain(["fs_base", "gs_base"].iter())
+        .map(|name| {
+            let value = general_value(regs, name).unwrap();
+            let natural = match *name {
+                "rip" => match debug_data.get_symbol_from_addr(value) {
+                    Some((symbol, 0)) => format!("{:#x} <{}>", value, symbol),
+                    Some((symbol, offset)) => format!("{:#x} <{}+{}>", value, symbol, offset),
+                    None => format!("{:#x}", value),
+                },
+                "rsp" | "rbp" => format!("{:#x}", value),
+                "eflags" => flag_names(value, &EFLAGS),
+                _ => (value as i64).to_string(),
+            };
+            (
+                name.to_string(),
+                row(name, &format!("{:#x}", value), &natural),
+            )
+        })
+        .collect()
+}
+
+/// Formats the x87, SSE and (given the XSAVE area) AVX registers as `info all-registers` rows.
+pub fn vector_rows(
+    fpregs: &libc::user_fpregs_struct,
+    xstate: Option<&[u8]>,
+) -> Vec<(String, String)> {
+    let mut rows = Vec::new();
+    for index in 0..8 {
+        let bytes = words_to_bytes(&fpregs.st_space[index * 4..index * 4 + 4]);
+        let raw: String = bytes[..10]
+            .iter()
+            .rev()
+            .map(|b| format!("{:02x}", b))
+            .collect();
+        let name = format!("st{}", index);
+        let value = format_float(extended_to_f64(&bytes));
+        let line = row(&name, &value, &format!("(raw 0x{})", raw));
+        rows.push((name, line));
+    }
+    let control = [
+        ("fctrl", fpregs.cwd as u64),
+        ("fstat", fpregs.swd as u64),
+        ("ftag", fpregs.ftw as u64),
+        ("fioff", fpregs.rip),
+        ("fooff", fpregs.rdp),
+        ("fop", fpregs.fop as u64),
+    ];
+    for (name, value) in control {
+        let line = row(name, &format!("{:#x}", value), &value.to_string());
+        rows.push((name.to_string(), line));
+    }
+    let mxcsr = fpregs.mxcsr as u64;
+    let line = row(
+        "mxcsr",
+        &format!("{:#x}", mxcsr),
+        &flag_names(mxcsr, &MXCSR),
+    );
+    rows.push(("mxcsr".to_string(), line));
+    for index in 0..16 {
+        let xmm = words_to_bytes(&fpregs.xmm_space[index * 4..index * 4 + 4]);
+        let name = format!("xmm{}", index);
+        let line = format!("{:<15}{}", name, format_vector(&xmm));
+        rows.push((name, line));
+    }
+    // The kernel leaves the ymm upper halves out of the XSAVE area when AVX has not been used
+    let avx = xstate.filter(|xstate| xstate.len() >= YMM_HI_OFFSET + 256);
+    if let Some(xstate) = avx {
+        let xstate_bv = u64::from_le_bytes(
+            xstate[XSTATE_BV_OFFSET..XSTATE_BV_OFFSET + 8]
+                .try_into()
+                .unwrap(),
+        );
+        for index in 0..16 {
+            let mut ymm = words_to_bytes(&fpregs.xmm_space[index * 4..index * 4 + 4]);
+            if xstate_bv & XSTATE_AVX != 0 {
+                let start = YMM_HI_OFFSET + index * 16;
+                ymm.extend_from_slice(&xstate[start..start + 16]);
+            } else {
+                ymm.extend_from_slice(&[0; 16]);
+            }
+            let name = format!("ymm{}", index);
+            let line = format!("{:<15}{}", name, format_vector(&ymm));
+            rows.push((name, line));
+        }
+    }
+    rows
+}
+
+fn row(name: &str, hex: &str, natural: &str) -> String {
+    format!("{:<15}{:<19}{}", name, hex, natural)
+}
+
+/// Lists the set bits of a flags register by name, e.g. `[ PF ZF IF ]`.
+fn flag_names(value: u64, flags: &[(u32, &str)]) -> String {
+    let names: Vec<&str> = flags
+        .iter()
+        .filter(|(bit, _)| value & (1 << bit) != 0)
+        .map(|(_, name)| *name)
+        .collect();
+    format!("[ {} ]", names.join(" "))
+}
+
+/// Formats a float, switching to exponent notation where writing out every digit would be long.
+fn format_float<T: Copy + Into<f64> + Display + LowerExp>(value: T) -> String {
+    let wide: f64 = value.into();
+    if wide == 0.0 || !wide.is_finite() || (1e-4..1e16).contains(&wide.abs()) {
+        value.to_string()
+    } else {
+        format!("{:e}", value)
+    }
+}
+
+fn words_to_bytes(words: &[u32]) -> Vec<u8> {
+    words.iter().flat_map(|word| word.to_le_bytes()).collect()
+}
+
+/// Formats a vector register as the ways it is commonly used: packed floats, doubles and
+/// integers.
+fn format_vector(bytes: &[u8]) -> String {
+    let floats: Vec<String> = bytes
+        .chunks_exact(4)
+        .map(|chunk| format_float(f32::from_le_bytes(chunk.try_into().unwrap())))
+        .collect();
+    let doubles: Vec<String> = bytes
+        .chunks_exact(8)
+        .map(|chunk| format_float(f64::from_le_bytes(chunk.try_into().unwrap())))
+        .collect();
+    let int32s: Vec<String> = bytes
+        .chunks_exact(4)
+        .map(|chunk| format!("{:#x}", u32::from_le_bytes(chunk.try_into().unwrap())))
+        .collect();
+    let int64s: Vec<String> = bytes
+        .chunks_exact(8)
+        .map(|chunk| format!("{:#x}", u64::from_le_bytes(chunk.try_into().unwrap())))
+        .collect();
+    format!(
+        "{{v{}_float = {{{}}}, v{}_double = {{{}}}, v{}_int32 = {{{}}}, v{}_int64 = {{{}}}}}",
+        floats.len(),
+        floats.join(", "),
+        doubles.len(),
+        doubles.join(", "),
+        int32s.len(),
+        int32s.join(", "),
+        int64s.len(),
+        int64s.join(", ")
+    )
+}
@@ -0,0 +1,83 @@
+//! Helpers for tests that run a real C program under the debugger.
+
+use crate::dwarf_data::DwarfData;
+use crate::inferior::Inferior;
+use std::ops::Deref;
+use std::path::PathBuf;
+use std::process::Command;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// An executable built by compile(). It lives in a directory of its own, which is deleted
+/// together with the executable and its source when the Program is dropped.
+pub struct Program {
+    dir: PathBuf,
+    path: String,
+}
+
+impl Deref for Program {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        &self.path
+    }
+}
+
+impl Drop for Program {
+    fn drop(&mut self) {
+        std::fs::remove_dir_all(&self.dir).ok();
+    }
+}
+
+/// Compiles a C program the way the Makefile builds the samples. Every call builds a separate
+/// executable, so that tests can run in parallel.
+pub fn compile(source: &str) -> Program {
+    static BUILDS: AtomicUsize = AtomicUsize::new(0);
+    let build = BUILDS.fetch_add(1, Ordering::Relaxed);
+    let dir = std::env::temp_dir().join(format!("deet-test-{}-{}", std::process::id(), build));
+    std::fs::create_dir_all(&dir).unwrap();
+    // Owned before anything can fail, so that the directory is removed even then
+    let program = Program {
+        path: dir.join("program").to_str().unwrap().to_string(),
+        dir,
+    };
+    let source_path = program.dir.join("program.c");
+    std::fs::write(&source_path, source).unwrap();
+    let status = Command::new("cc")
+        .args([
+            "-O0",
+            "-g",
+            "-no-pie",
+            "-fno-omit-frame-pointer",
+            "-o",
+            &program.path,
+        ])
+        .arg(&source_path)
+        .status()
+        .unwrap();
+    assert!(status.success());
+    program
+}
+
+/// A program built by compile() and started under the debugger, stopped before its first
+/// instruction. The inferior is killed when the Debuggee is dropped.
+pub struct Debuggee {
+    pub debug_data: DwarfData,
+    pub inferior: Inferior,
+    _program: Program,
+}
+
+impl Drop for Debuggee {
+    fn drop(&mut self) {
+        self.inferior.kill();
+    }
+}
+
+/// Compiles a C program and starts it, with no breakpoints set.
+pub fn start(source: &str) -> Debuggee {
+    let program = compile(source);
+    Debuggee {
+        debug_data: DwarfData::from_file(&program).unwrap(),
+        inferior: Inferior::new(&program, &[], &[]).unwrap(),
+        _program: program,
+    }
+}
@@ -0,0 +1,342 @@
+//! Call frame information (`.eh_frame` and `.debug_frame`), which describes for every instruction
+//! how to find the canonical frame address (CFA) of the frame executing it and where the caller's
+//! registers were saved. Stacks are unwound with it one frame at a time.
+
+use crate::dwarf_data::{DwarfData, Symbol};
+use crate::inferior::Inferior;
+use gimli::{
+    BaseAddresses, CfaRule, DebugFrame, EhFrame, EndianSlice, RegisterRule, RunTimeEndian,
+    UnwindContext, UnwindSection, UnwindTableRow,
+};
+use nix::sys::ptrace;
+use object::{Object, ObjectSection, ObjectSegment, ObjectSymbol, SymbolKind};
+use std::fs;
+
+type Reader<'a> = EndianSlice<'a, RunTimeEndian>;
+
+// Guards against looping forever on a corrupt stack
+const MAX_FRAMES: usize = 4096;
+
+// The registers a callee must preserve, plus the return address column, in DWARF numbering:
+// rbx, rbp, r12-r15 and rip
+const CALLEE_SAVED: [u16; 7] = [3, 6, 12, 13, 14, 15, 16];
+const RETURN_ADDRESS: u16 = 16;
+
+/// The call frame information of one object file.
+pub struct CallFrameInfo {
+    eh_frame: Vec<u8>,
+    debug_frame: Vec<u8>,
+    bases: BaseAddresses,
+    /// How far the object was moved from the addresses it was linked at, for shared libraries
+    bias: u64,
+}
+
+impl CallFrameInfo {
+    pub fn load(object: &object::File, bias: u64) -> CallFrameInfo {
+        let section = |name: &str| object.section_by_name(name);
+        let data = |name: &str| {
+            section(name)
+                .and_then(|section| section.uncompressed_data().ok())
+                .map_or_else(Vec::new, |data| data.into_owned())
+        };
+        let address = |name: &str| section(name).map_or(0, |section| section.address());
+        CallFrameInfo {
+            eh_frame: data(".eh_frame"),
+            debug_frame: data(".debug_frame"),
+            bases: BaseAddresses::default()
+                .set_eh_frame(address(".eh_frame"))
+                .set_eh_frame_hdr(address(".eh_frame_hdr"))
+                .set_text(address(".text"))
+                .set_got(address(".got")),
+            bias,
+        }
+    }
+
+    /// Finds the unwind rules in effect at pc. `.debug_frame` is preferred, since it is more
+    /// complete where both exist.
+    fn row(&self, pc: u64) -> Option<UnwindTableRow<Reader<'_>>> {
+        let pc = pc.wrapping_sub(self.bias);
+        let mut debug_frame = DebugFrame::new(&self.debug_frame, RunTimeEndian::Little);
+        debug_frame.set_address_size(8);
+        let mut eh_frame = EhFrame::new(&self.eh_frame, RunTimeEndian::Little);
+        eh_frame.set_address_size(8);
+        find_row(&debug_frame, &self.bases, pc).or_else(|| find_row(&eh_frame, &self.bases, pc))
+    }
+
+    /// Computes the CFA of the frame executing pc: the value of the stack pointer just before
+    /// the call that created the frame.
+    pub fn cfa(&self, pc: u64, regs: &libc::user_regs_struct) -> Option<u64> {
+        cfa(&self.row(pc)?, regs)
+    }
+
+    /// Recovers the registers of the caller of the frame executing pc, whose registers are regs.
+    /// Returns None if there are no unwind rules for pc, and Some(None) if the frame is the
+    /// outermost one (its return address is undefined, as in `_start`).
+    pub fn caller(
+        &self,
+        pc: u64,
+        regs: &libc::user_regs_struct,
+        inferior: &Inferior,
+    ) -> Option<Option<libc::user_regs_struct>> {
+        let row = self.row(pc)?;
+        let cfa = cfa(&row, regs)?;
+        let mut caller = *regs;
+        for register in CALLEE_SAVED {
+            let value = match row.register(gimli::Register(register)) {
+                RegisterRule::Undefined if register == RETURN_ADDRESS => return Some(None),
+                RegisterRule::Offset(offset) => {
+                    inferior.read_word(cfa.wrapping_add(offset as u64)).ok()?
+                }
+                RegisterRule::ValOffset(offset) => cfa.wrapping_add(offset as u64),
+                RegisterRule::Register(other) => register_value(regs, other.0)?,
+                // Registers without a rule were not touched by the callee
+                _ => continue,
+            };
+            set_register_value(&mut caller, register, value);
+        }
+        caller.rsp = cfa;
+        Some(Some(caller))
+    }
+}
+
+fn find_row<'a, Section: UnwindSection<Reader<'a>>>(
+    section: &Section,
+    bases: &BaseAddresses,
+    pc: u64,
+) -> Option<UnwindTableRow<Reader<'a>>> {
+    let fde = section
+        .fde_for_address(bases, pc, Section::cie_from_offset)
+        .ok()?;
+    let mut context = UnwindContext::new();
+    fde.unwind_info_for_address(section, bases, &mut context, pc)
+        .ok()
+        .cloned()
+}
+
+fn cfa(row: &UnwindTableRow<Reader<'_>>, regs: &libc::user_regs_struct) -> Option<u64> {
+    match row.cfa() {
+        CfaRule::RegisterAndOffset { register, offset } => {
+            Some(register_value(regs, register.0)?.wrapping_add(*offset as u64))
+        }
+        // Only used by a few hand-written functions such as the PLT stubs
+        CfaRule::Expression(_) => None,
+    }
+}
+
+/// An object file mapped into the inferior, as listed in `/proc/<pid>/maps`.
+struct Mapping {
+    start: u64,
+    end: u64,
+    /// The offset into the file of the mapped data
+    offset: u64,
+    path: String,
+    /// Loaded the first time a frame in the mapping is unwound
+    library: Option<Option<Library>>,
+}
+
+/// What is known about a shared library without debugging information.
+struct Library {
+    frame_info: CallFrameInfo,
+    /// Function symbols at their run-time addresses, sorted by address
+    symbols: Vec<Symbol>,
+}
+
+/// One frame of the inferior's stack.
+pub struct StackFrame {
+    /// For frames other than the innermost, only rip, rsp and the callee-saved registers are
+    /// meaningful
+    pub regs: libc::user_regs_struct,
+    /// The address used to look up the frame's function, line and variables: rip for the
+    /// innermost frame, and rip - 1 for its callers. The rip of a caller is a return address,
+    /// which may already belong to the next line or even the next function.
+    pub pc: u64,
+    /// The shared library the frame is executing in, for frames outside the executable
+    pub library: Option<String>,
+    /// The name of the library function the frame is executing
+    pub symbol: Option<String>,
+}
+
+/// Unwinds the stack of the stopped inferior, returning its frames innermost first.
+///
+/// Frames in the executable are unwound with its call frame information; frames in shared
+/// libraries with theirs, loaded on demand. Code without any falls back to following the rbp
+/// chain.
+pub fn unwind(inferior: &Inferior, debug_data: &DwarfData) -> Result<Vec<StackFrame>, nix::Error> {
+    let mut regs = ptrace::getregs(inferior.pid())?;
+    let mut mappings = read_maps(inferior);
+    let mut frames = Vec::new();
+    while frames.len() < MAX_FRAMES {
+        let pc = if frames.is_empty() {
+            regs.rip
+        } else {
+            regs.rip - 1
+        };
+        let mut frame = StackFrame {
+            regs,
+            pc,
+            library: None,
+            symbol: None,
+        };
+        let caller = match debug_data.get_caller(pc, &regs, inferior) {
+            Some(caller) => caller,
+            None => {
+                let library = library_at(&mut mappings, pc);
+                let caller = match library {
+                    Some((_, Some(library))) => library.frame_info.caller(pc, &regs, inferior),
+                    _ => None,
+                };
+                if debug_data.get_symbol_from_addr(pc).is_none() {
+                    if let Some((path, library)) = library {
+                        frame.library = Some(path.to_string());
+                        frame.symbol = library.and_then(|library| library.symbol_at(pc));
+                    }
+                }
+                caller.unwrap_or_else(|| frame_pointer_caller(&regs, inferior))
+            }
+        };
+        frames.push(frame);
+        match caller {
+            // A caller whose stack is not above ours means the unwind went wrong
+            Some(caller) if caller.rip != 0 && caller.rsp > regs.rsp => regs = caller,
+            _ => break,
+        }
+    }
+    Ok(frames)
+}
+
+/// Unwinds a frame that has no call frame information, assuming it saved the caller's rbp at
+/// rbp with the return address just above it.
+fn frame_pointer_caller(
+    regs: &libc::user_regs_struct,
+    inferior: &Inferior,
+) -> Option<libc::user_regs_struct> {
+    if regs.rbp <= regs.rsp {
+        return None;
+    }
+    let mut caller = *regs;
+    caller.rip = inferior.read_word(regs.rbp + 8).ok()?;
+    caller.rbp = inferior.read_word(regs.rbp).ok()?;
+    caller.rsp = regs.rbp + 16;
+    Some(caller)
+}
+
+fn read_maps(inferior: &Inferior) -> Vec<Mapping> {
+    let maps = fs::read_to_string(format!("/proc/{}/maps", inferior.pid())).unwrap_or_default();
+    maps.lines()
+        .filter_map(|line| {
+            // start-end perms offset dev inode path
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let (range, perms, offset, path) = (
+                fields.first()?,
+                fields.get(1)?,
+                fields.get(2)?,
+                fields.get(5)?,
+            );
+            if !perms.contains('x') || !path.starts_with('/') {
+                return None;
+            }
+            let (start, end) = range.split_once('-')?;
+            Some(Mapping {
+                start: u64::from_str_radix(start, 16).ok()?,
+                end: u64::from_str_radix(end, 16).ok()?,
+                offset: u64::from_str_radix(offset, 16).ok()?,
+                path: path.to_string(),
+                library: None,
+            })
+        })
+        .collect()
+}
+
+/// Finds the object file mapped at pc, returning its path and, if it could be read, its
+/// contents.
+fn library_at(mappings: &mut [Mapping], pc: u64) -> Option<(&str, Option<&Library>)> {
+    let mapping = mappings
+        .iter_mut()
+        .find(|mapping| mapping.start <= pc && pc < mapping.end)?;
+    if mapping.library.is_none() {
+        mapping.library = Some(Library::load(mapping));
+    }
+    Some((&mapping.path, mapping.library.as_ref()?.as_ref()))
+}
+
+impl Library {
+    fn load(mapping: &Mapping) -> Option<Library> {
+        let data = fs::read(&mapping.path).ok()?;
+        let object = object::File::parse(&*data).ok()?;
+        // Find the segment that was mapped here. Its file offset and address differ by the
+        // same amount in memory, except for the bias the whole object was loaded at.
+        let segment = object.segments().find(|segment| {
+            let (offset, size) = segment.file_range();
+            offset & !0xfff <= mapping.offset && mapping.offset < offset + size
+        })?;
+        let (offset, _) = segment.file_range();
+        let linked = segment
+            .address()
+            .wrapping_sub(offset)
+            .wrapping_add(mapping.offset);
+        let bias = mapping.start.wrapping_sub(linked);
+        let mut symbols: Vec<Symbol> = object
+            .symbols()
+            .chain(object.dynamic_symbols())
+            .filter(|sym| sym.kind() == SymbolKind::Text && sym.address() != 0)
+            .filter_map(|sym| {
+                Some(Symbol {
+                    name: sym.name().ok().filter(|name| !name.is_empty())?.to_string(),
+                    address: sym.address().wrapping_add(bias),
+                    size: sym.size(),
+                })
+            })
+            .collect();
+        symbols.sort_by_key(|sym| sym.address);
+        Some(Library {
+            frame_info: CallFrameInfo::load(&object, bias),
+            symbols,
+        })
+    }
+
+    fn symbol_at(&self, pc: u64) -> Option<String> {
+        let index = self.symbols.partition_point(|sym| sym.address <= pc);
+        self.symbols[..index]
+            .iter()
+            .rev()
+            .find(|sym| pc < sym.address + sym.size.max(1))
+            .map(|sym| sym.name.clone())
+    }
+}
+
+/// Reads a general purpose register by its DWARF number.
+pub fn register_value(regs: &libc::user_regs_struct, register: u16) -> Option<u64> {
+    let mut regs = *regs;
+    register_slot(&mut regs, register).map(|value| *value)
+}
+
+/// Changes a general purpose register by its DWARF number. Other registers are left alone.
+pub fn set_register_value(regs: &mut libc::user_regs_struct, register: u16, value: u64) {
+    if let Some(slot) = register_slot(regs, register) {
+        *slot = value;
+    }
+}
+
+// The DWARF register numbering of the x86-64 System V ABI
+fn register_slot(regs: &mut libc::user_regs_struct, register: u16) -> Option<&mut u64> {
+    Some(match register {
+        0 => &mut regs.rax,
+        1 => &mut regs.rdx,
+        2 => &mut regs.rcx,
+        3 => &mut regs.rbx,
+        4 => &mut regs.rsi,
+        5 => &mut regs.rdi,
+        6 => &mut regs.rbp,
+        7 => &mut regs.rsp,
+        8 => &mut regs.r8,
+        9 => &mut regs.r9,
+        10 => &mut regs.r10,
+        11 => &mut regs.r11,
+        12 => &mut regs.r12,
+        13 => &mut regs.r13,
+        14 => &mut regs.r14,
+        15 => &mut regs.r15,
+        16 => &mut regs.rip,
+        _ => return None,
+    })
+}
@@ -0,0 +1,470 @@
+//! Formats values read from the inferior according to their DWARF type, the way GDB prints them.
+
+use crate::dwarf_data::{DwarfData, Member, Pointee, Type, TypeKind, Variable};
+use crate::expr;
+use crate::inferior::Inferior;
+use crate::location::Frame;
+use crate::memory::PAGE_SIZE;
+
+// Runs of more than this many equal elements are shown as `<repeats N times>`
+const REPEAT_THRESHOLD: usize = 10;
+// At most this many array elements or string characters are printed
+const PRINT_ELEMENTS: usize = 200;
+
+/// Formats a value of type ty whose bytes were read from the inferior. Pointers other than
+/// strings are prefixed with their type, e.g. `(int *) 0x404040 <counter>`.
+pub fn format_value(
+    ty: &Type,
+    bytes: &[u8],
+    inferior: &Inferior,
+    debug_data: &DwarfData,
+) -> String {
+    let formatter = Formatter {
+        inferior,
+        debug_data,
+    };
+    let value = formatter.format(ty, bytes);
+    match &ty.strip().kind {
+        TypeKind::Pointer(pointee) if !formatter.is_string(pointee) => {
+            format!("({}) {}", ty.name, value)
+        }
+        _ => value,
+    }
+}
+
+/// Formats a value like format_value, but without prefixing pointers with their type, for when
+/// the type is shown separately.
+pub fn format_bare(ty: &Type, bytes: &[u8], inferior: &Inferior, debug_data: &DwarfData) -> String {
+    Formatter {
+        inferior,
+        debug_data,
+    }
+    .format(ty, bytes)
+}
+
+/// Formats a function argument for a backtrace. Like GDB, only scalars are shown: aggregates
+/// are abbreviated to `...`.
+pub fn format_argument(
+    ty: &Type,
+    bytes: &[u8],
+    inferior: &Inferior,
+    debug_data: &DwarfData,
+) -> String {
+    match ty.strip().kind {
+        TypeKind::Struct(_) | TypeKind::Union(_) | TypeKind::Array(..) => "...".to_string(),
+        _ => format_bare(ty, bytes, inferior, debug_data),
+    }
+}
+
+/// Formats the current value of a variable in frame with one of the functions above. Values
+/// that cannot be read are shown the way GDB shows them.
+pub fn format_variable(var: &Variable, frame: &Frame, format: FormatFn) -> String {
+    match expr::read_variable(var, frame) {
+        Ok(value) if value.optimized_out => "<optimized out>".to_string(),
+        Ok(value) => format(
+            &value.entity_type,
+            &value.bytes,
+            frame.inferior,
+            frame.debug_data,
+        ),
+        Err(e) => format!("<error: {}>", e),
+    }
+}
+
+/// The signature shared by format_value and its variants.
+pub type FormatFn = fn(&Type, &[u8], &Inferior, &DwarfData) -> String;
+
+struct Formatter<'a> {
+    inferior: &'a Inferior,
+    debug_data: &'a DwarfData,
+}
+
+impl Formatter<'_> {
+    fn format(&self, ty: &Type, bytes: &[u8]) -> String {
+        match &ty.kind {
+            TypeKind::Typedef(target) | TypeKind::Const(target) | TypeKind::Volatile(target) => {
+                self.format(target, bytes)
+            }
+            TypeKind::Base => format_base(ty, bytes),
+            TypeKind::Void => "void".to_string(),
+            TypeKind::Enum(enumerators) => {
+                let value = ty.to_i64(to_word(bytes));
+                match enumerators.iter().find(|e| e.value == value) {
+                    Some(enumerator) => enumerator.name.clone(),
+                    None => value.to_string(),
+                }
+            }
+            TypeKind::Pointer(pointee) => self.format_pointer(pointee, to_word(bytes)),
+            TypeKind::Struct(members) | TypeKind::Union(members) => {
+                let fields: Vec<String> = members
+                    .iter()
+                    .map(|member| {
+                        format!("{} = {}", member.name, self.format_member(member, bytes))
+                    })
+                    .collect();
+                format!("{{{}}}", fields.join(", "))
+            }
+            TypeKind::Array(element, count) => self.format_array(element, *count, bytes),
+            TypeKind::Function => {
+                format!("{{{}}} {}", ty.name, self.format_address(to_word(bytes)))
+            }
+        }
+    }
+
+    fn format_member(&self, member: &Member, bytes: &[u8]) -> String {
+        let start = (member.offset as usize).min(bytes.len());
+        let end = (start + member.entity_type.size as usize).min(bytes.len());
+        let bit_size = match member.bit_size {
+            Some(bit_size) => bit_size,
+            None => return self.format(&member.entity_type, &bytes[start..end]),
+        };
+        // Bit fields are shifted down and masked, then formatted like a full-width value
+        let raw = to_word(&bytes[start..bytes.len().min(start + 8)]) >> member.bit_offset;
+        let raw = if bit_size >= 64 {
+            raw
+        } else {
+            let mask = (1u64 << bit_size) - 1;
+            let signed = matches!(
+                member.entity_type.encoding,
+                Some(gimli::DW_ATE_signed) | Some(gimli::DW_ATE_signed_char)
+            );
+            if signed && raw & (1 << (bit_size - 1)) != 0 {
+                raw | !mask
+            } else {
+                raw & mask
+            }
+        };
+        self.format(&member.entity_type, &raw.to_le_bytes()[..end - start])
+    }
+
+    fn format_array(&self, element: &Type, count: Option<u64>, bytes: &[u8]) -> String {
+        if is_char(element) {
+            let mut chars = bytes.to_vec();
+            // Like GDB, a terminating NUL is not shown
+            if chars.last() == Some(&0) {
+                chars.pop();
+            }
+            return format_string(&chars, false);
+        }
+        let size = element.size.max(1) as usize;
+        let count = count.map_or(bytes.len() / size, |count| count as usize);
+        let elements: Vec<String> = bytes
+            .chunks_exact(size)
+            .take(count.min(PRINT_ELEMENTS))
+            .map(|chunk| self.format(element, chunk))
+            .collect();
+        let mut parts = Vec::new();
+        let mut index = 0;
+        while index < elements.len() {
+            let run = elements[index..]
+                .iter()
+                .take_while(|e| **e == elements[index])
+                .count();
+            if run > REPEAT_THRESHOLD {
+                parts.push(format!("{} <repeats {} times>", elements[index], run));
+            } else {
+                parts.extend(elements[index..index + run].iter().cloned());
+            }
+            index += run;
+        }
+        let truncated = count > PRINT_ELEMENTS;
+        format!(
+            "{{{}{}}}",
+            parts.join(", "),
+            if truncated { "..." } else { "" }
+        )
+    }
+
+    fn format_pointer(&self, pointee: &Pointee, addr: u64) -> String {
+        if addr == 0 || !self.is_string(pointee) {
+            return self.format_address(addr);
+        }
+        match self.read_string(addr) {
+            Ok((chars, complete)) => {
+                format!("{:#x} {}", addr, format_string(&chars, !complete))
+            }
+            Err(_) => format!(
+                "{:#x} <error: Cannot access memory at address {:#x}>",
+                addr, addr
+            ),
+        }
+    }
+
+    /// Formats an address, followed by the symbol it points into if there is one.
+    fn format_address(&self, addr: u64) -> String {
+        match self.debug_data.get_symbol_from_addr(addr) {
+            Some((name, 0)) if addr != 0 => format!("{:#x} <{}>", addr, name),
+            Some((name, offset)) if addr != 0 => format!("{:#x} <{}+{}>", addr, name, offset),
+            _ => format!("{:#x}", addr),
+        }
+    }
+
+    fn is_string(&self, pointee: &Pointee) -> bool {
+        self.debug_data.get_pointee(pointee).is_some_and(is_char)
+    }
+
+    /// Reads a NUL-terminated string of at most PRINT_ELEMENTS characters. Also returns whether
+    /// the whole string was read.
+    fn read_string(&self, addr: u64) -> Result<(Vec<u8>, bool), nix::Error> {
+        let mut chars = Vec::new();
+        while chars.len() < PRINT_ELEMENTS {
+            // Read up to the end of the page, so that a string ending just before unmapped
+            // memory is not mistaken for an unreadable one
+            let start = addr + chars.len() as u64;
+            let len = (PAGE_SIZE - start % PAGE_SIZE).min((PRINT_ELEMENTS - chars.len()) as u64);
+            for byte in self.inferior.read_bytes(start, len as usize)? {
+                if byte == 0 {
+                    return Ok((chars, true));
+                }
+                chars.push(byte);
+            }
+        }
+        Ok((chars, false))
+    }
+}
+
+/// Reads the NUL-terminated string at addr and formats it as a literal. Also returns the number
+/// of bytes it takes up, including the NUL if the whole string was read.
+pub fn format_c_string(
+    addr: u64,
+    inferior: &Inferior,
+    debug_data: &DwarfData,
+) -> Result<(String, u64), nix::Error> {
+    let formatter = Formatter {
+        inferior,
+        debug_data,
+    };
+    let (chars, complete) = formatter.read_string(addr)?;
+    let len = chars.len() as u64 + complete as u64;
+    Ok((format_string(&chars, !complete), len))
+}
+
+fn format_base(ty: &Type, bytes: &[u8]) -> String {
+    let raw = to_word(bytes);
+    match ty.encoding {
+        Some(gimli::DW_ATE_signed_char) | Some(gimli::DW_ATE_unsigned_char) if ty.size == 1 => {
+            format!("{} {}", ty.to_i64(raw), char_literal(raw as u8))
+        }
+        // x87 extended precision, stored in 10 bytes padded to 16
+        Some(gimli::DW_ATE_float) if ty.size > 8 && bytes.len() >= 10 => {
+            format!("{}", extended_to_f64(bytes))
+        }
+        _ => ty.format_scalar(raw),
+    }
+}
+
+fn is_char(ty: &Type) -> bool {
+    let ty = ty.strip();
+    ty.size == 1
+        && matches!(
+            ty.encoding,
+            Some(gimli::DW_ATE_signed_char) | Some(gimli::DW_ATE_unsigned_char)
+        )
+}
+
+/// Formats characters as a string literal, collapsing long runs of one character like GDB:
+/// `"hi", '\000' <repeats 13 times>`.
+fn format_string(chars: &[u8], truncated: bool) -> String {
+    let mut parts = Vec::new();
+    let mut literal = String::new();
+    let mut index = 0;
+    while index < chars.len() {
+        let run = chars[index..]
+            .iter()
+            .take_while(|c| **c == chars[index])
+            .count();
+        if run > REPEAT_THRESHOLD {
+            if !literal.is_empty() {
+                parts.push(format!("\"{}\"", literal));
+                literal.clear();
+            }
+            parts.push(format!(
+                "{} <repeats {} times>",
+                char_literal(chars[index]),
+                run
+            ));
+        } else {
+            for c in &chars[index..index + run] {
+                literal.push_str(&escape_char(*c, '"'));
+            }
+        }
+        index += run;
+    }
+    if !literal.is_empty() || parts.is_empty() {
+        parts.push(format!("\"{}\"", literal));
+    }
+    format!("{}{}", parts.join(", "), if truncated { "..." } else { "" })
+}
+
+pub fn char_literal(c: u8) -> String {
+    format!("'{}'", escape_char(c, '\''))
+}
+
+fn escape_char(c: u8, quote: char) -> String {
+    match c {
+        b'\n' => "\\n".to_string(),
+        b'\t' => "\\t".to_string(),
+        b'\r' => "\\r".to_string(),
+        0x07 => "\\a".to_string(),
+        0x08 => "\\b".to_string(),
+        0x0c => "\\f".to_string(),
+        0x0b => "\\v".to_string(),
+        0x1b => "\\033".to_string(),
+        b'\\' => "\\\\".to_string(),
+        c if c as char == quote => format!("\\{}", quote),
+        c if c.is_ascii_graphic() || c == b' ' => (c as char).to_string(),
+        c => format!("\\{:03o}", c),
+    }
+}
+
+/// Reads up to 8 little-endian bytes as a word.
+pub fn to_word(bytes: &[u8]) -> u64 {
+    let mut word = [0u8; 8];
+    let len = bytes.len().min(8);
+    word[..len].copy_from_slice(&bytes[..len]);
+    u64::from_le_bytes(word)
+}
+
+/// Converts an x87 80-bit extended precision float to the nearest f64.
+pub fn extended_to_f64(bytes: &[u8]) -> f64 {
+    let mantissa = to_word(&bytes[..8]);
+    let sign_exponent = u16::from_le_bytes([bytes[8], bytes[9]]);
+    let sign = if sign_exponent & 0x8000 != 0 {
+        -1.0
+    } else {
+        1.0
+    };
+    let exponent = (sign_exponent & 0x7fff) as i32;
+    if exponent == 0x7fff {
+        return if mantissa << 1 == 0 {
+            sign * f64::INFINITY
+        } else {
+            f64::NAN
+        };
+    }
+    // The integer bit is explicit, so the value is mantissa * 2^(exponent - bias - 63). The
+    // power of two is applied in steps, as it underflows on its own for tiny values.
+    let mut value = mantissa as f64 * 2f64.powi(-63);
+    let mut exponent = exponent.max(1) - 16383;
+    while exponent < -1000 {
+        value *= 2f64.powi(-1000);
+        exponent += 1000;
+    }
+    sign * value * 2f64.powi(exponent)
+}
+
+/// Converts an f64 to x87 extended precision, padded to 16 bytes like a `long double`.
+pub fn f64_to_extended(value: f64) -> [u8; 16] {
+    let bits = value.to_bits();
+    let sign = ((bits >> 63) as u16) << 15;
+    let exponent = ((bits >> 52) & 0x7ff) as i32;
+    let fraction = bits & ((1 << 52) - 1);
+    let (exponent, mantissa) = match exponent {
+        0 if fraction == 0 => (0, 0),
+        // Subnormal doubles are normal in extended precision
+        0 => {
+            let shift = fraction.leading_zeros();
+            (1 - 1023 + 16383 - (shift as i32 - 11), fraction << shift)
+        }
+        0x7ff => (0x7fff, (1 << 63) | (fraction << 11)),
+        _ => (exponent - 1023 + 16383, (1 << 63) | (fraction << 11)),
+    };
+    let mut bytes = [0u8; 16];
+    bytes[..8].copy_from_slice(&mantissa.to_le_bytes());
+    bytes[8..10].copy_from_slice(&(sign | exponent as u16).to_le_bytes());
+    bytes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dwarf_data::Location;
+    use crate::testing;
+
+    #[test]
+    fn extended_round_trip() {
+        for value in [
+            0.0,
+            1.0,
+            -2.5,
+            0.1,
+            1e300,
+            -1e-300,
+            5e-324,
+            f64::MAX,
+            f64::INFINITY,
+        ] {
+            assert_eq!(extended_to_f64(&f64_to_extended(value)), value);
+        }
+        assert!(extended_to_f64(&f64_to_extended(f64::NAN)).is_nan());
+        assert_eq!(
+            extended_to_f64(&f64_to_extended(-0.0)).to_bits(),
+            (-0.0f64).to_bits()
+        );
+    }
+
+    #[test]
+    fn extended_layout() {
+        // 1.0 has an explicit integer bit and the biased exponent 16383
+        let mut one = [0u8; 16];
+        one[7] = 0x80;
+        one[8..10].copy_from_slice(&0x3fffu16.to_le_bytes());
+        assert_eq!(f64_to_extended(1.0), one);
+        // -3.0 is -1.5 * 2^1
+        let mut bytes = [0u8; 16];
+        bytes[7] = 0xc0;
+        bytes[8..10].copy_from_slice(&0xc000u16.to_le_bytes());
+        assert_eq!(extended_to_f64(&bytes), -3.0);
+    }
+
+    #[test]
+    fn strings_with_repeats() {
+        assert_eq!(format_string(b"hi", false), "\"hi\"");
+        assert_eq!(format_string(b"", false), "\"\"");
+        assert_eq!(format_string(b"a\n\"\x01", false), "\"a\\n\\\"\\001\"");
+        assert_eq!(format_string(&[b'x'; 10], false), "\"xxxxxxxxxx\"");
+        assert_eq!(format_string(&[b'x'; 11], false), "'x' <repeats 11 times>");
+        let mut chars = b"hi".to_vec();
+        chars.extend([0; 13]);
+        chars.extend(b"yo");
+        assert_eq!(
+            format_string(&chars, true),
+            "\"hi\", '\\000' <repeats 13 times>, \"yo\"..."
+        );
+    }
+
+    #[test]
+    fn char_literals() {
+        assert_eq!(char_literal(b'A'), "'A'");
+        assert_eq!(char_literal(b'\''), "'\\''");
+        assert_eq!(char_literal(b'"'), "'\"'");
+        assert_eq!(char_literal(0x1b), "'\\033'");
+        assert_eq!(char_literal(0xff), "'\\377'");
+    }
+
+    #[test]
+    fn long_strings_are_truncated() {
+        let text = "0123456789".repeat(100);
+        let debuggee = testing::start(&format!(
+            "char text[] = \"{}\";\nint main(void) {{ return 0; }}\n",
+            text
+        ));
+        let (debug_data, inferior) = (&debuggee.debug_data, &debuggee.inferior);
+        let addr = match debug_data
+            .get_variable_at_addr(0, "text")
+            .unwrap()
+            .0
+            .location
+        {
+            Location::Address(addr) => addr,
+            _ => panic!("text is not at a fixed address"),
+        };
+        let (literal, len) = format_c_string(addr, inferior, debug_data).unwrap();
+        assert_eq!(literal, format!("\"{}\"...", &text[..PRINT_ELEMENTS]));
+        assert_eq!(len, PRINT_ELEMENTS as u64);
+        // The end of the string fits, so it is shown whole, with its NUL counted
+        let (literal, len) = format_c_string(addr + 900, inferior, debug_data).unwrap();
+        assert_eq!(literal, format!("\"{}\"", &text[900..]));
+        assert_eq!(len, 101);
+    }
+}
@@ -0,0 +1,181 @@
+use crate::dwarf_data::{DwarfData, Type};
+use crate::inferior::Inferior;
+use crate::value;
+use std::fmt;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum WatchKind {
+    /// `watch`: stop when the value is changed
+    Write,
+    /// `rwatch`: stop when the value is read
+    Read,
+    /// `awatch`: stop when the value is read or written
+    Access,
+}
+
+impl WatchKind {
+    /// The name of this kind of watchpoint, as used in `info breakpoints`.
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            WatchKind::Write => "hw watchpoint",
+            WatchKind::Read => "read watchpoint",
+            WatchKind::Access => "acc watchpoint",
+        }
+    }
+
+    /// The heading printed when a watchpoint of this kind is set or triggers.
+    pub fn description(&self) -> &'static str {
+        match self {
+            WatchKind::Write => "Hardware watchpoint",
+            WatchKind::Read => "Hardware read watchpoint",
+            WatchKind::Access => "Hardware access (read/write) watchpoint",
+        }
+    }
+}
+
+/// Why no debug register could be programmed for a hardware watchpoint.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HwUnavailable {
+    /// All four debug registers hold other watchpoints
+    NoFreeRegister,
+    /// A debug register only covers 1, 2, 4 or 8 bytes aligned to their length
+    Uncovered,
+}
+
+impl fmt::Display for HwUnavailable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HwUnavailable::NoFreeRegister => write!(f, "all hardware debug registers are in use"),
+            HwUnavailable::Uncovered => write!(
+                f,
+                "the value is not 1, 2, 4 or 8 bytes long and aligned to its size"
+            ),
+        }
+    }
+}
+
+/// The stack frame a watched local variable lives in. The watchpoint is deleted once the frame
+/// returns, since the memory no longer holds the variable after that.
+#[derive(Clone, Copy)]
+pub struct Scope {
+    /// The frame's canonical frame address, which is where the stack pointer is once the frame
+    /// has returned
+    pub cfa: u64,
+    /// Where the frame returns to in its caller; a trap is kept there to notice the return
+    pub return_addr: u64,
+}
+
+/// A watchpoint created with `watch`, `rwatch` or `awatch`.
+pub struct Watchpoint {
+    pub num: usize,
+    pub kind: WatchKind,
+    /// The watched expression as the user typed it
+    pub expression: String,
+    pub addr: u64,
+    pub entity_type: Type,
+    pub enabled: bool,
+    pub hit_count: u64,
+    /// The debug register (DR0-DR3) holding this watchpoint while it is inserted
+    pub slot: Option<usize>,
+    /// Set when no debug register can watch the value, so the inferior is single-stepped and
+    /// the value compared after every instruction instead
+    pub software: bool,
+    /// Set for local variables
+    pub scope: Option<Scope>,
+    /// The value last seen at addr, used to report changes
+    pub old_value: Vec<u8>,
+}
+
+impl Watchpoint {
+    /// The name of this watchpoint's type, as used in `info breakpoints`.
+    pub fn type_name(&self) -> &'static str {
+        if self.software {
+            "watchpoint"
+        } else {
+            self.kind.type_name()
+        }
+    }
+
+    /// The heading printed when this watchpoint is set or triggers.
+    pub fn description(&self) -> &'static str {
+        if self.software {
+            "Watchpoint"
+        } else {
+            self.kind.description()
+        }
+    }
+
+    /// Inserts this watchpoint into the inferior and records the current value. A debug register
+    /// is used when one is free and can cover the value; otherwise a `watch` falls back to a
+    /// software watchpoint. A debug register is tried again on every insertion, since one may
+    /// have been freed since, e.g. by rerunning the program. Returns why the watchpoint could not
+    /// be inserted at all, which only happens for `rwatch` and `awatch`.
+    pub fn insert(
+        &mut self,
+        inferior: &mut Inferior,
+    ) -> Result<Result<(), HwUnavailable>, nix::Error> {
+        match inferior.insert_hw_watchpoint(self.addr, self.entity_type.size, self.kind)? {
+            Ok(slot) => {
+                self.slot = Some(slot);
+                self.software = false;
+            }
+            Err(reason) if self.kind != WatchKind::Write => return Ok(Err(reason)),
+            Err(_) => self.software = true,
+        }
+        if let Some(scope) = self.scope {
+            inferior.set_breakpoint(scope.return_addr)?;
+        }
+        self.old_value = self.read_value(inferior)?;
+        Ok(Ok(()))
+    }
+
+    /// Frees the debug register holding this watchpoint, if any.
+    pub fn remove(&mut self, inferior: &mut Inferior) -> Result<(), nix::Error> {
+        if let Some(slot) = self.slot.take() {
+            inferior.remove_hw_watchpoint(slot)?;
+        }
+        Ok(())
+    }
+
+    /// Reads the current value of the watched memory.
+    pub fn read_value(&self, inferior: &Inferior) -> Result<Vec<u8>, nix::Error> {
+        inferior.read_bytes(self.addr, self.entity_type.size as usize)
+    }
+
+    /// Called when the debug register for this watchpoint fired, or for software watchpoints
+    /// after every instruction. Reports the access and returns true if the inferior should
+    /// stop; a `watch` that was written with an unchanged value does not stop, matching GDB.
+    pub fn triggered(&mut self, inferior: &Inferior, debug_data: &DwarfData) -> bool {
+        let new_value = match self.read_value(inferior) {
+            Ok(value) => value,
+            Err(e) => {
+                println!("Could not read watchpoint {} value: {}", self.num, e);
+                return true;
+            }
+        };
+        let changed = new_value != self.old_value;
+        if self.kind == WatchKind::Write && !changed {
+            return false;
+        }
+        // The debug register of an `rwatch` also fires on writes. Like GDB, a write is told
+        // apart by the value changing, and only remembered.
+        if self.kind == WatchKind::Read && changed {
+            self.old_value = new_value;
+            return false;
+        }
+        println!();
+        println!("{} {}: {}", self.description(), self.num, self.expression);
+        println!();
+        let format =
+            |bytes: &[u8]| value::format_value(&self.entity_type, bytes, inferior, debug_data);
+        if changed && self.kind != WatchKind::Read {
+            println!("Old value = {}", format(&self.old_value));
+            println!("New value = {}", format(&new_value));
+        } else {
+            println!("Value = {}", format(&new_value));
+        }
+        self.old_value = new_value;
+        self.hit_count += 1;
+        true
+    }
+}